@@ -0,0 +1,291 @@
+//! Module [`AccessLog`].
+//! One line per completed exchange in Common Log Format, Combined Log Format or JSON,
+//! written to stdout or to file with rotation by size and time
+use crate::http::{method::Method, request::Request, uri::Uri, version::Version};
+use serde::Serialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, Result, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub(crate) const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Format of access log line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessFormat {
+    /// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`
+    Common,
+    /// Common format with `"referer" "user-agent"` at the end
+    Combined,
+    /// Object with all fields of [`AccessEntry`]
+    Json,
+}
+
+/// Configuration of access log
+#[derive(Clone, Debug)]
+pub struct AccessLog {
+    pub format: AccessFormat,
+    /// File of log, stdout is used if it is missing
+    pub path: Option<String>,
+    /// Size of file in bytes after which it is rotated
+    pub max_size: Option<u64>,
+    /// Age of file after which it is rotated
+    pub max_age: Option<Duration>,
+}
+
+impl AccessLog {
+    /// Create access log writing to stdout
+    pub fn new(format: AccessFormat) -> Self {
+        Self {
+            format,
+            path: None,
+            max_size: None,
+            max_age: None,
+        }
+    }
+
+    /// Write log to file, rotated files get suffix with unix time like `access.log.1700000000`
+    pub fn with_file(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Rotate file when its size exceeds `max_size` bytes
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Rotate file when it is older than `max_age`
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Open file of log
+    pub fn open(&self) -> Result<AccessLogger> {
+        let output = match &self.path {
+            Some(path) => Some(Output::open(path)?),
+            None => None,
+        };
+        Ok(AccessLogger {
+            config: self.clone(),
+            output: Mutex::new(output),
+        })
+    }
+}
+
+/// Fields of one exchange, headers of request are not kept
+/// so credentials like `Authorization` and `Cookie` don't get to log
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessEntry {
+    /// Start of exchange in RFC 3339 like `2000-10-10T13:55:36Z`
+    pub time: String,
+    pub client_ip: String,
+    pub request_id: String,
+    /// First line of request as it is received from client
+    pub request_line: String,
+    pub status: u16,
+    /// Size of response body
+    pub bytes_sent: usize,
+    /// Address of target, empty if request is not proxied
+    pub upstream: String,
+    pub duration_ms: u128,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub method: Method,
+    pub uri: Uri,
+    pub version: Version,
+    #[serde(skip)]
+    started: SystemTime,
+    #[serde(skip)]
+    instant: Instant,
+}
+
+impl AccessEntry {
+    /// Start entry of request received from client
+    pub fn new(req: &Request, client_ip: &str) -> Self {
+        let started = SystemTime::now();
+        Self {
+            time: format_rfc3339(started),
+            client_ip: client_ip.to_string(),
+            request_id: String::new(),
+            request_line: String::from_utf8_lossy(
+                req.headers
+                    .raw()
+                    .split(|b| *b == b'\n')
+                    .next()
+                    .unwrap_or_default(),
+            )
+            .trim_end()
+            .to_string(),
+            status: 0,
+            bytes_sent: 0,
+            upstream: String::new(),
+            duration_ms: 0,
+            referer: req.headers.get_header("Referer"),
+            user_agent: req.headers.get_header("User-Agent"),
+            method: req.method.clone(),
+            uri: req.uri.clone(),
+            version: req.version,
+            started,
+            instant: Instant::now(),
+        }
+    }
+
+    /// Set result of exchange and its duration
+    pub fn finish(&mut self, status: u16, bytes_sent: usize) {
+        self.status = status;
+        self.bytes_sent = bytes_sent;
+        self.duration_ms = self.instant.elapsed().as_millis();
+    }
+
+    /// Create line of log without line break
+    pub fn format(&self, format: AccessFormat) -> Result<String> {
+        let bytes = match self.bytes_sent {
+            0 => "-".to_string(),
+            size => size.to_string(),
+        };
+        let common = format!(
+            "{} - - [{}] \"{}\" {} {}",
+            self.client_ip,
+            format_clf(self.started),
+            escape(&self.request_line),
+            self.status,
+            bytes
+        );
+        let quoted = |value: &Option<String>| match value {
+            Some(value) => format!("\"{}\"", escape(value)),
+            None => "\"-\"".to_string(),
+        };
+        Ok(match format {
+            AccessFormat::Common => common,
+            AccessFormat::Combined => format!(
+                "{} {} {}",
+                common,
+                quoted(&self.referer),
+                quoted(&self.user_agent)
+            ),
+            AccessFormat::Json => serde_json::to_string(self).map_err(Error::other)?,
+        })
+    }
+}
+
+/// Writer of access log shared by connection handlers
+#[derive(Debug)]
+pub struct AccessLogger {
+    config: AccessLog,
+    output: Mutex<Option<Output>>,
+}
+
+impl AccessLogger {
+    /// Write line of entry and rotate file if it is needed
+    pub fn write(&self, entry: &AccessEntry) -> Result<()> {
+        let line = format!("{}\n", entry.format(self.config.format)?);
+        let mut output = self.output.lock().unwrap();
+        let (output, path) = match (output.as_mut(), &self.config.path) {
+            (Some(output), Some(path)) => (output, path),
+            _ => {
+                print!("{}", line);
+                return Ok(());
+            }
+        };
+
+        let too_big = self
+            .config
+            .max_size
+            .map(|max| output.size > 0 && output.size + line.len() as u64 > max)
+            .unwrap_or(false);
+        let too_old = self
+            .config
+            .max_age
+            .map(|max| output.opened.elapsed() >= max)
+            .unwrap_or(false);
+        if too_big || too_old {
+            *output = output.rotate(path)?;
+        }
+
+        output.file.write_all(line.as_bytes())?;
+        output.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Output {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl Output {
+    fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            size: file.metadata()?.len(),
+            file,
+            opened: Instant::now(),
+        })
+    }
+
+    /// Rename current file and open new one
+    fn rotate(&mut self, path: &str) -> Result<Self> {
+        self.file.flush()?;
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut rotated = format!("{}.{}", path, secs);
+        let mut n = 1;
+        while Path::new(&rotated).exists() {
+            rotated = format!("{}.{}.{}", path, secs, n);
+            n += 1;
+        }
+        fs::rename(path, rotated)?;
+        Output::open(path)
+    }
+}
+
+/// Escape quotes and control characters of value inside of quotes
+fn escape(value: &str) -> String {
+    value.escape_default().to_string()
+}
+
+/// UTC date and time as year, month, day, hour, minute and second
+fn to_utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rest) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since 1970-01-01, algorithm of Howard Hinnant
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
+
+/// Time like `2000-10-10T13:55:36Z`
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let (year, month, day, hour, min, sec) = to_utc(time);
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{min:02}:{sec:02}Z")
+}
+
+/// Time like `10/Oct/2000:13:55:36 +0000`
+pub fn format_clf(time: SystemTime) -> String {
+    let (year, month, day, hour, min, sec) = to_utc(time);
+    let month = MONTHS[month as usize - 1];
+    format!("{day:02}/{month}/{year:04}:{hour:02}:{min:02}:{sec:02} +0000")
+}
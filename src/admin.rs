@@ -0,0 +1,442 @@
+//! Module [`Admin`].
+//! Admin server listening on separate address with metrics and JSON API
+use crate::{
+    http::{
+        connector::Target,
+        headers::{Header, Headers},
+        limits::LimitError,
+        method::Method,
+        status::Status,
+        Http,
+    },
+    log::{Log, LogLevel},
+    metrics::Metrics,
+    router::Router,
+    shutdown::Shutdown,
+    upstream::{Upstream, UpstreamState},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Result, Write},
+    net::{Shutdown as SocketShutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const TAG: &str = "Admin";
+
+/// Max time of reading request or writing response, so stuck client doesn't block admin listener
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Max size of request line and headers in bytes
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+/// Max size of request body in bytes, larger bodies are rejected with `413`
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Content type of Prometheus text format
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Content type of JSON API
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Open client connection
+#[derive(Debug)]
+struct Connection {
+    client: SocketAddr,
+    opened: Instant,
+    requests: u64,
+    /// Connection waits for next request, new one is busy until handler reads it
+    idle: bool,
+    /// Clone of client socket to close connection on shutdown
+    socket: Option<TcpStream>,
+}
+
+/// Registry of open client connections
+#[derive(Debug, Default)]
+pub struct Connections {
+    next: AtomicU64,
+    list: Mutex<BTreeMap<u64, Connection>>,
+}
+
+/// Connection registered in [`Connections`], it is removed when dropped
+pub struct ConnectionGuard {
+    id: u64,
+    connections: Arc<Connections>,
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register accepted connection of client
+    pub fn open(self: &Arc<Self>, socket: &TcpStream) -> Result<ConnectionGuard> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        self.list.lock().unwrap().insert(
+            id,
+            Connection {
+                client: socket.peer_addr()?,
+                opened: Instant::now(),
+                requests: 0,
+                idle: false,
+                socket: socket.try_clone().ok(),
+            },
+        );
+        Ok(ConnectionGuard {
+            id,
+            connections: Arc::clone(self),
+        })
+    }
+
+    /// Count of open connections
+    pub fn len(&self) -> usize {
+        self.list.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Close connections waiting for next request
+    pub fn close_idle(&self) {
+        for conn in self.list.lock().unwrap().values() {
+            if conn.idle {
+                conn.close();
+            }
+        }
+    }
+
+    /// Close all connections including ones with requests in progress
+    pub fn close_all(&self) {
+        for conn in self.list.lock().unwrap().values() {
+            conn.close();
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let list = self.list.lock().unwrap();
+        let connections: Vec<Value> = list
+            .iter()
+            .map(|(id, conn)| {
+                json!({
+                    "id": id,
+                    "client": conn.client.to_string(),
+                    "durationMs": conn.opened.elapsed().as_millis() as u64,
+                    "requests": conn.requests,
+                    "idle": conn.idle,
+                })
+            })
+            .collect();
+        json!({ "active": list.len(), "connections": connections })
+    }
+}
+
+impl Connection {
+    fn close(&self) {
+        if let Some(socket) = &self.socket {
+            let _ = socket.shutdown(SocketShutdown::Both);
+        }
+    }
+}
+
+impl ConnectionGuard {
+    /// Replace address of client, for example by one from PROXY protocol header
+    pub fn set_client(&self, client: SocketAddr) {
+        self.update(|conn| conn.client = client);
+    }
+
+    /// Mark connection as waiting for next request
+    pub fn idle(&self) {
+        self.update(|conn| conn.idle = true);
+    }
+
+    /// Count request served on connection
+    pub fn request(&self) {
+        self.update(|conn| {
+            conn.requests += 1;
+            conn.idle = false;
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Connection)) {
+        if let Some(conn) = self.connections.list.lock().unwrap().get_mut(&self.id) {
+            f(conn);
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.list.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Body of requests changing targets
+#[derive(Debug, Deserialize)]
+struct TargetBody {
+    address: String,
+    weight: Option<u32>,
+    /// Index of group in list of `GET /targets`, default group is `0`
+    group: Option<usize>,
+}
+
+/// Body of request changing log level
+#[derive(Debug, Deserialize)]
+struct LogLevelBody {
+    level: String,
+}
+
+/// Server of admin endpoints, one connection is served at a time:
+/// - `GET /metrics` metrics in Prometheus text format
+/// - `GET /targets` groups of targets with their health
+/// - `POST /targets` add target `{"address", "weight"?, "group"?}`
+/// - `POST /targets/drain` stop new requests to target `{"address", "group"?}`
+/// - `GET /log-level`, `PUT /log-level` get or change level `{"level"}`
+/// - `GET /connections` open client connections
+///
+/// Requests changing targets or log level need `Authorization: Bearer <token>`
+/// if token is set by [`Admin::with_token`]
+#[derive(Clone)]
+pub struct Admin {
+    pub metrics: Arc<Metrics>,
+    pub router: Arc<Router>,
+    pub connections: Arc<Connections>,
+    /// Log of proxy server, its level is changed by API
+    pub log: Log,
+    /// Bearer token of requests changing proxy
+    pub token: Option<String>,
+}
+
+impl Admin {
+    pub fn new(
+        metrics: Arc<Metrics>,
+        router: Arc<Router>,
+        connections: Arc<Connections>,
+        log: Log,
+    ) -> Self {
+        Self {
+            metrics,
+            router,
+            connections,
+            log,
+            token: None,
+        }
+    }
+
+    /// Require bearer token for `POST /targets`, `POST /targets/drain` and `PUT /log-level`
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Serve connections of listener in new thread until shutdown
+    pub fn spawn(self, listener: TcpListener, shutdown: Shutdown) -> JoinHandle<()> {
+        if let Ok(address) = listener.local_addr() {
+            shutdown.register(address);
+        }
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if shutdown.is_triggered() {
+                    break;
+                }
+                let res = stream.and_then(|stream| self.handle(stream));
+                if let Err(err) = res {
+                    self.log
+                        .println(LogLevel::Warn, TAG, "Error in admin request", err);
+                }
+            }
+        })
+    }
+
+    /// Answer one request and close connection
+    fn handle(&self, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut client = Http::from(stream);
+        let head = match client.read_headers_timeout(TIMEOUT, TIMEOUT, MAX_HEAD_SIZE) {
+            Ok(head) if head.is_empty() => return Ok(()),
+            Ok(head) => head,
+            Err(e) => match LimitError::status(&e) {
+                Some(code) => return Admin::write_error(&mut client, code),
+                None => return Err(e),
+            },
+        };
+        let headers = Headers::from(head);
+        let len = headers.content_length().unwrap_or(0);
+        if len > MAX_BODY_SIZE {
+            return Admin::write_error(&mut client, 413);
+        }
+        let mut body = vec![0; len];
+        client.read_exact(&mut body)?;
+
+        let method = headers.method().map(Method::as_str).unwrap_or("");
+        let path = headers.target().unwrap_or("/");
+        let path = path.split('?').next().unwrap_or(path);
+        self.log.println(LogLevel::Info, TAG, method, path);
+
+        if path == "/metrics" {
+            return match method {
+                "GET" => Admin::write_response(
+                    &mut client,
+                    200,
+                    METRICS_CONTENT_TYPE,
+                    &self.metrics.render(),
+                ),
+                _ => Admin::write_json(&mut client, 405, json!({"error": "Method Not Allowed"})),
+            };
+        }
+        if matches!(
+            (method, path),
+            ("POST", "/targets" | "/targets/drain") | ("PUT", "/log-level")
+        ) && !self.is_authorized(&headers)
+        {
+            return Admin::write_error(&mut client, 401);
+        }
+        let (code, value) = match (method, path) {
+            ("GET", "/targets") => (200, self.targets()),
+            ("POST", "/targets") => self.add_target(&body),
+            ("POST", "/targets/drain") => self.drain_target(&body),
+            ("GET", "/log-level") => (200, json!({"level": format!("{:?}", self.log.level())})),
+            ("PUT", "/log-level") => self.set_log_level(&body),
+            ("GET", "/connections") => (200, self.connections.to_json()),
+            (_, "/targets" | "/targets/drain" | "/log-level" | "/connections") => {
+                (405, json!({"error": "Method Not Allowed"}))
+            }
+            _ => (404, json!({"error": "Not Found"})),
+        };
+        Admin::write_json(&mut client, code, value)
+    }
+
+    /// Check bearer token of request, all requests are allowed without token
+    fn is_authorized(&self, headers: &Headers) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let value = headers.get_header("authorization").unwrap_or_default();
+        let Some((scheme, credentials)) = value.split_once(' ') else {
+            return false;
+        };
+        // Every byte is compared, so time of check doesn't reveal matching prefix
+        let (credentials, token) = (credentials.trim().as_bytes(), token.as_bytes());
+        scheme.eq_ignore_ascii_case("bearer")
+            && credentials.len() == token.len()
+            && credentials
+                .iter()
+                .zip(token)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    fn targets(&self) -> Value {
+        let groups: Vec<Value> = self
+            .router
+            .groups()
+            .iter()
+            .enumerate()
+            .map(|(index, (route, balancer))| {
+                let targets: Vec<Value> = balancer
+                    .upstreams()
+                    .iter()
+                    .map(|s| target_json(s))
+                    .collect();
+                json!({ "group": index, "route": route, "targets": targets })
+            })
+            .collect();
+        json!({ "groups": groups })
+    }
+
+    fn add_target(&self, body: &[u8]) -> (u16, Value) {
+        let body: TargetBody = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(e) => return (400, json!({"error": e.to_string()})),
+        };
+        let groups = self.router.groups();
+        let Some((_, balancer)) = groups.get(body.group.unwrap_or(0)) else {
+            return (404, json!({"error": "Group not found"}));
+        };
+        if let Err(e) = Target::parse(&body.address) {
+            return (400, json!({"error": e.to_string()}));
+        }
+        let upstream = Upstream::new(&body.address).with_weight(body.weight.unwrap_or(1));
+        self.log
+            .println(LogLevel::Info, TAG, "Add target", &upstream.address);
+        (201, target_json(&balancer.add_upstream(upstream)))
+    }
+
+    fn drain_target(&self, body: &[u8]) -> (u16, Value) {
+        let body: TargetBody = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(e) => return (400, json!({"error": e.to_string()})),
+        };
+        let groups = self.router.groups();
+        match groups.get(body.group.unwrap_or(0)) {
+            Some((_, balancer)) if balancer.drain(&body.address) => {
+                self.log
+                    .println(LogLevel::Info, TAG, "Drain target", &body.address);
+                (200, json!({"address": body.address, "draining": true}))
+            }
+            _ => (404, json!({"error": "Target not found"})),
+        }
+    }
+
+    fn set_log_level(&self, body: &[u8]) -> (u16, Value) {
+        let level = serde_json::from_slice::<LogLevelBody>(body)
+            .map_err(|e| e.to_string())
+            .and_then(|body| body.level.parse::<LogLevel>().map_err(|e| e.to_string()));
+        match level {
+            Ok(level) => {
+                self.log.set_level(level);
+                (200, json!({"level": format!("{:?}", level)}))
+            }
+            Err(e) => (400, json!({"error": e})),
+        }
+    }
+
+    /// Answer with reason phrase of status as error
+    fn write_error(client: &mut Http, code: u16) -> Result<()> {
+        let status = Status::new(code);
+        Admin::write_json(client, status.code, json!({ "error": status.text }))
+    }
+
+    fn write_json(client: &mut Http, code: u16, value: Value) -> Result<()> {
+        Admin::write_response(client, code, JSON_CONTENT_TYPE, &value.to_string())
+    }
+
+    fn write_response(client: &mut Http, code: u16, content_type: &str, body: &str) -> Result<()> {
+        let list = vec![
+            Header {
+                name: "Content-Type".to_string(),
+                value: content_type.to_string(),
+            },
+            Header {
+                name: "Content-Length".to_string(),
+                value: body.len().to_string(),
+            },
+            Header {
+                name: "Connection".to_string(),
+                value: "close".to_string(),
+            },
+        ];
+        let headers = Headers::new_response(&Status::new(code), list);
+        client.write_all(headers.raw())?;
+        client.write_all(body.as_bytes())?;
+        client.flush()
+    }
+}
+
+fn target_json(state: &UpstreamState) -> Value {
+    json!({
+        "address": state.upstream.address,
+        "weight": state.upstream.weight,
+        "healthy": state.is_healthy(),
+        "draining": state.is_draining(),
+        "active": state.active(),
+    })
+}
@@ -0,0 +1,572 @@
+//! Module [`Cache`].
+//! Cache of target responses by `Cache-Control`, `Expires` and `Vary` with revalidation
+//! by `ETag` and `Last-Modified`, least recently used responses are evicted
+use crate::{
+    access_log::MONTHS,
+    http::{body::Body, header_map::HeaderMap, headers::Headers, method::Method, request::Request},
+    prelude::constants::{CACHE_MAX_ENTRY_SIZE, CACHE_MAX_SIZE},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Result,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Statuses of responses which can be stored
+const STORABLE_STATUSES: [u16; 8] = [200, 203, 204, 300, 301, 308, 404, 410];
+
+/// Headers of one connection or one delivery, they are not stored
+const SKIPPED_HEADERS: [&str; 5] = [
+    "Connection",
+    "Keep-Alive",
+    "Transfer-Encoding",
+    "Age",
+    "X-Cache",
+];
+
+/// Request headers making response specific to request, such requests bypass cache
+const BYPASS_HEADERS: [&str; 7] = [
+    "Authorization",
+    "Range",
+    "If-Match",
+    "If-None-Match",
+    "If-Modified-Since",
+    "If-Unmodified-Since",
+    "If-Range",
+];
+
+/// Extension of files of bodies in cache directory
+const BODY_EXTENSION: &str = "body";
+
+/// Configuration of cache
+#[derive(Clone, Debug)]
+pub struct Cache {
+    /// Max size of all stored responses in bytes
+    pub max_size: usize,
+    /// Max size of one response body in bytes, bigger responses are not stored
+    pub max_entry_size: usize,
+    /// Directory of response bodies, they are kept in memory if it is missing
+    pub dir: Option<String>,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache::new()
+    }
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self {
+            max_size: CACHE_MAX_SIZE,
+            max_entry_size: CACHE_MAX_ENTRY_SIZE,
+            dir: None,
+        }
+    }
+
+    pub fn with_max_size(mut self, size: usize) -> Self {
+        self.max_size = size;
+        self
+    }
+
+    pub fn with_max_entry_size(mut self, size: usize) -> Self {
+        self.max_entry_size = size;
+        self
+    }
+
+    /// Keep response bodies in files of directory, index of them is kept in memory
+    pub fn with_dir(mut self, dir: &str) -> Self {
+        self.dir = Some(dir.to_string());
+        self
+    }
+
+    /// Create directory of bodies, files of bodies left by previous run are removed
+    pub fn open(&self) -> Result<CacheStore> {
+        if let Some(dir) = &self.dir {
+            fs::create_dir_all(dir)?;
+            for file in fs::read_dir(dir)? {
+                let path = file?.path();
+                if path.extension().is_some_and(|e| e == BODY_EXTENSION) {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        Ok(CacheStore {
+            config: self.clone(),
+            inner: Mutex::new(Inner::default()),
+        })
+    }
+}
+
+/// Response from cache ready to be sent to client
+#[derive(Clone, Debug)]
+pub struct Cached {
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Time since response was received or revalidated
+    pub age: Duration,
+    id: u64,
+}
+
+impl Cached {
+    /// Add `If-None-Match` and `If-Modified-Since` to request revalidating response
+    pub fn conditional(&self, req: &Headers) -> Result<Headers> {
+        let map = self.headers.header_map();
+        let mut req = req.clone();
+        if let Some(etag) = map.get("ETag") {
+            req = req.set_header("If-None-Match", etag)?;
+        }
+        if let Some(modified) = map.get("Last-Modified") {
+            req = req.set_header("If-Modified-Since", modified)?;
+        }
+        Ok(req)
+    }
+}
+
+/// Result of looking for response of request
+#[derive(Debug)]
+pub enum Lookup {
+    /// Request can't be answered from cache
+    Bypass,
+    /// There is no stored response
+    Miss,
+    /// Stored response is fresh
+    Hit(Cached),
+    /// Stored response must be revalidated by target
+    Stale(Cached),
+}
+
+/// Stored responses shared by workers
+#[derive(Debug)]
+pub struct CacheStore {
+    config: Cache,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: u64,
+    tick: u64,
+    size: usize,
+    entries: HashMap<u64, Entry>,
+    /// Ids of responses by key, responses differ by headers of `Vary`
+    keys: HashMap<String, Vec<u64>>,
+    /// Ids of responses by tick of last use
+    lru: BTreeMap<u64, u64>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    key: String,
+    headers: Headers,
+    /// Body is missing if it is kept in file
+    body: Option<Vec<u8>>,
+    size: usize,
+    /// Lower case names of headers of `Vary` with their values in request
+    vary: Vec<(String, Option<String>)>,
+    freshness: Freshness,
+    tick: u64,
+}
+
+/// Time during which response is used without revalidation
+#[derive(Debug)]
+struct Freshness {
+    stored: SystemTime,
+    /// Value of `Age` of response
+    initial_age: Duration,
+    lifetime: Duration,
+    /// Response is revalidated before every use
+    no_cache: bool,
+}
+
+impl CacheStore {
+    /// Key of request by host and target
+    pub fn key(req: &Request) -> String {
+        let host = req.headers.get_header("Host").unwrap_or_default();
+        format!("{}{}", host.to_lowercase(), req.uri)
+    }
+
+    /// Find stored response for `GET` or `HEAD` request without body
+    pub fn lookup(&self, key: &str, req: &Request) -> Lookup {
+        if !matches!(req.method, Method::Get | Method::Head) || Body::request(req) != Body::Empty {
+            return Lookup::Bypass;
+        }
+        let map = req.headers.header_map();
+        if BYPASS_HEADERS.iter().any(|name| map.contains(name)) {
+            return Lookup::Bypass;
+        }
+        let directives = directives(&map);
+        if has_directive(&directives, "no-store") {
+            return Lookup::Bypass;
+        }
+        let max_age = directive_secs(&directives, "max-age");
+        let revalidate = has_directive(&directives, "no-cache")
+            || map
+                .get("Pragma")
+                .is_some_and(|v| v.eq_ignore_ascii_case("no-cache"));
+
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.keys.get(key).and_then(|ids| {
+            ids.iter()
+                .find(|id| {
+                    inner.entries[*id]
+                        .vary
+                        .iter()
+                        .all(|(n, v)| *v == joined(&map, n))
+                })
+                .copied()
+        });
+        let id = match id {
+            Some(id) => id,
+            None => return Lookup::Miss,
+        };
+        inner.touch(id);
+
+        let entry = &inner.entries[&id];
+        let age = entry.freshness.age();
+        let fresh = !revalidate
+            && entry.freshness.is_fresh(age)
+            && max_age.map_or(true, |max_age| age <= max_age);
+        let validated = entry.headers.list().iter().any(|h| {
+            h.name.eq_ignore_ascii_case("ETag") || h.name.eq_ignore_ascii_case("Last-Modified")
+        });
+        let headers = entry.headers.clone();
+        let body = match &entry.body {
+            Some(body) => body.clone(),
+            None => match fs::read(self.path(id)) {
+                Ok(body) => body,
+                Err(_) => {
+                    inner.remove(id, None);
+                    return Lookup::Miss;
+                }
+            },
+        };
+        let cached = Cached {
+            headers,
+            body,
+            age,
+            id,
+        };
+        match (fresh, validated) {
+            (true, _) => Lookup::Hit(cached),
+            (false, true) => Lookup::Stale(cached),
+            (false, false) => Lookup::Miss,
+        }
+    }
+
+    /// Check that response to request can be stored before its body is read
+    pub fn is_storable(&self, req: &Request, status: u16, res: &Headers, body: &Body) -> bool {
+        if req.method != Method::Get || !STORABLE_STATUSES.contains(&status) {
+            return false;
+        }
+        match body {
+            Body::Empty | Body::Chunked => {}
+            Body::Length(len) if *len <= self.config.max_entry_size => {}
+            _ => return false,
+        }
+        let map = res.header_map();
+        let directives = directives(&map);
+        if has_directive(&directives, "no-store")
+            || has_directive(&directives, "private")
+            || map.contains("Set-Cookie")
+            || tokens(&map, "Vary").any(|v| v == "*")
+        {
+            return false;
+        }
+        // Response without explicit freshness is stored only if it can be revalidated
+        lifetime(&map, &directives).is_some()
+            || map.contains("ETag")
+            || map.contains("Last-Modified")
+    }
+
+    /// Store response checked by [`CacheStore::is_storable`], `req` is request as it was received
+    /// from client, so `Vary` is matched by its headers. Returns stored response
+    pub fn store(&self, key: &str, req: &Request, res: &Headers, body: Vec<u8>) -> Result<Cached> {
+        // Freshness takes `Age` of target, which is not stored with other headers
+        let freshness = Freshness::new(&res.header_map());
+        let mut headers = res.header_map();
+        for name in SKIPPED_HEADERS {
+            headers.remove(name);
+        }
+        headers.insert("Content-Length", &body.len().to_string())?;
+        let headers = res.with_header_map(headers)?;
+        let cached = Cached {
+            headers: headers.clone(),
+            body,
+            age: freshness.age(),
+            id: 0,
+        };
+        if cached.body.len() > self.config.max_entry_size {
+            return Ok(cached);
+        }
+
+        let req_map = req.headers.header_map();
+        let res_map = headers.header_map();
+        let vary: Vec<(String, Option<String>)> = tokens(&res_map, "Vary")
+            .map(|name| {
+                let value = joined(&req_map, &name);
+                (name, value)
+            })
+            .collect();
+        let size = cached.body.len() + headers.raw().len();
+
+        let mut inner = self.inner.lock().unwrap();
+        // Response replaces one with the same key and values of `Vary`
+        let replaced: Vec<u64> = inner
+            .keys
+            .get(key)
+            .map(|ids| {
+                ids.iter()
+                    .filter(|id| inner.entries[*id].vary == vary)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default();
+        for id in replaced {
+            inner.remove(id, self.config.dir.as_ref().map(|_| self.path(id)));
+        }
+
+        inner.next_id += 1;
+        let id = inner.next_id;
+        let body = match &self.config.dir {
+            Some(_) => {
+                fs::write(self.path(id), &cached.body)?;
+                None
+            }
+            None => Some(cached.body.clone()),
+        };
+        inner.entries.insert(
+            id,
+            Entry {
+                key: key.to_string(),
+                headers,
+                body,
+                size,
+                vary,
+                freshness,
+                tick: 0,
+            },
+        );
+        inner.keys.entry(key.to_string()).or_default().push(id);
+        inner.size += size;
+        inner.touch(id);
+
+        while inner.size > self.config.max_size {
+            let oldest = match inner.lru.values().next() {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+            inner.remove(oldest, self.config.dir.as_ref().map(|_| self.path(oldest)));
+        }
+        Ok(Cached { id, ..cached })
+    }
+
+    /// Update stored response by headers of `304 Not Modified` received on revalidation
+    pub fn revalidate(&self, cached: &Cached, res: &Headers) -> Result<Cached> {
+        let mut headers = cached.headers.header_map();
+        let res_map = res.header_map();
+        for (name, value) in res_map.iter() {
+            if !SKIPPED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name))
+                && !name.eq_ignore_ascii_case("Content-Length")
+            {
+                headers.insert(name, value)?;
+            }
+        }
+        let headers = cached.headers.with_header_map(headers)?;
+        let mut updated = headers.header_map();
+        if let Some(age) = res_map.get("Age") {
+            updated.insert("Age", age)?;
+        }
+        let freshness = Freshness::new(&updated);
+        let age = freshness.age();
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.entries.get_mut(&cached.id) {
+            entry.freshness = freshness;
+            entry.headers = headers.clone();
+        }
+        Ok(Cached {
+            headers,
+            body: cached.body.clone(),
+            age,
+            id: cached.id,
+        })
+    }
+
+    /// Remove all responses with key, it is called after unsafe requests like `POST`
+    pub fn invalidate(&self, key: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let ids = inner.keys.get(key).cloned().unwrap_or_default();
+        for id in ids {
+            inner.remove(id, self.config.dir.as_ref().map(|_| self.path(id)));
+        }
+    }
+
+    /// Max size of one stored response body in bytes
+    pub fn max_entry_size(&self) -> usize {
+        self.config.max_entry_size
+    }
+
+    /// Count of stored responses
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Size of stored responses in bytes
+    pub fn size(&self) -> usize {
+        self.inner.lock().unwrap().size
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        let dir = self.config.dir.as_deref().unwrap_or("");
+        Path::new(dir).join(format!("{id}.{BODY_EXTENSION}"))
+    }
+}
+
+impl Inner {
+    /// Mark response as the most recently used
+    fn touch(&mut self, id: u64) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(&id) {
+            self.lru.remove(&entry.tick);
+            entry.tick = tick;
+            self.lru.insert(tick, id);
+        }
+    }
+
+    fn remove(&mut self, id: u64, file: Option<PathBuf>) {
+        let entry = match self.entries.remove(&id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        self.lru.remove(&entry.tick);
+        self.size -= entry.size;
+        if let Some(ids) = self.keys.get_mut(&entry.key) {
+            ids.retain(|i| *i != id);
+            if ids.is_empty() {
+                self.keys.remove(&entry.key);
+            }
+        }
+        if let Some(file) = file {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+impl Freshness {
+    fn new(headers: &HeaderMap) -> Self {
+        let directives = directives(headers);
+        Self {
+            stored: SystemTime::now(),
+            initial_age: Duration::from_secs(
+                headers.get("Age").and_then(|v| v.parse().ok()).unwrap_or(0),
+            ),
+            lifetime: lifetime(headers, &directives).unwrap_or_default(),
+            no_cache: has_directive(&directives, "no-cache"),
+        }
+    }
+
+    fn age(&self) -> Duration {
+        self.initial_age + self.stored.elapsed().unwrap_or_default()
+    }
+
+    fn is_fresh(&self, age: Duration) -> bool {
+        !self.no_cache && age < self.lifetime
+    }
+}
+
+/// Explicit freshness lifetime by `s-maxage`, `max-age` or `Expires`
+fn lifetime(headers: &HeaderMap, directives: &[(String, Option<String>)]) -> Option<Duration> {
+    if let Some(age) = directive_secs(directives, "s-maxage") {
+        return Some(age);
+    }
+    if let Some(age) = directive_secs(directives, "max-age") {
+        return Some(age);
+    }
+    let expires = headers.get("Expires")?;
+    let date = headers
+        .get("Date")
+        .and_then(parse_http_date)
+        .unwrap_or_else(SystemTime::now);
+    // Invalid date like `0` means already expired
+    Some(
+        parse_http_date(expires)
+            .and_then(|expires| expires.duration_since(date).ok())
+            .unwrap_or_default(),
+    )
+}
+
+/// Lower case directives of `Cache-Control` with values without quotes
+fn directives(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    tokens(headers, "Cache-Control")
+        .map(|d| match d.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_string(),
+                Some(value.trim().trim_matches('"').to_string()),
+            ),
+            None => (d, None),
+        })
+        .collect()
+}
+
+fn has_directive(directives: &[(String, Option<String>)], name: &str) -> bool {
+    directives.iter().any(|(n, _)| n == name)
+}
+
+/// Value of directive like `max-age=60` in seconds
+fn directive_secs(directives: &[(String, Option<String>)], name: &str) -> Option<Duration> {
+    directives
+        .iter()
+        .find(|(n, _)| n == name)
+        .and_then(|(_, v)| v.as_ref()?.parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// Lower case comma separated elements of all headers with name
+fn tokens<'a>(headers: &'a HeaderMap, name: &'a str) -> impl Iterator<Item = String> + 'a {
+    headers
+        .get_all(name)
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+}
+
+/// Values of all headers with name joined by comma
+fn joined(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<&str> = headers.get_all(name).collect();
+    match values.is_empty() {
+        true => None,
+        false => Some(values.join(", ")),
+    }
+}
+
+/// Parse time like `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let mut parts = value.split_whitespace().skip(1);
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|v| v.parse::<u64>().ok());
+    let (hour, min, sec) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+    // Days since epoch of civil date
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3600 + min * 60 + sec))
+}
@@ -0,0 +1,89 @@
+//! Module [`ForwardProxy`].
+//! Forward proxy mode with absolute-form requests and `CONNECT` tunnels
+use crate::{
+    http::{
+        connector::Target,
+        uri::{Uri, UriForm},
+    },
+    router::HostMatch,
+};
+
+/// Destination host and port of forward proxy requests
+#[derive(Clone, Debug)]
+pub struct Destination {
+    /// Host matched exactly or by wildcard, [`None`] matches any host
+    pub host: Option<HostMatch>,
+    /// Port of destination, [`None`] matches any port
+    pub port: Option<u16>,
+}
+
+impl Destination {
+    /// Create destination with host like `example.com`, `*.example.com` or `*` for any host
+    pub fn new(host: &str, port: Option<u16>) -> Self {
+        Self {
+            host: match host {
+                "*" => None,
+                _ => Some(HostMatch::new(host)),
+            },
+            port,
+        }
+    }
+
+    pub fn is_match(&self, host: &str, port: u16) -> bool {
+        let host = host.to_lowercase();
+        self.host
+            .as_ref()
+            .map(|h| h.is_match(&host))
+            .unwrap_or(true)
+            && self.port.map(|p| p == port).unwrap_or(true)
+    }
+}
+
+/// Configuration of forward proxy mode.
+/// Destination is allowed if it doesn't match deny list and allow list is empty or matches it
+#[derive(Clone, Debug, Default)]
+pub struct ForwardProxy {
+    pub allow: Vec<Destination>,
+    pub deny: Vec<Destination>,
+}
+
+impl ForwardProxy {
+    /// Create forward proxy allowing any destination
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow destination, other destinations are denied after first call
+    pub fn with_allow(mut self, host: &str, port: Option<u16>) -> Self {
+        self.allow.push(Destination::new(host, port));
+        self
+    }
+
+    /// Deny destination even if it is allowed
+    pub fn with_deny(mut self, host: &str, port: Option<u16>) -> Self {
+        self.deny.push(Destination::new(host, port));
+        self
+    }
+
+    /// Check access to destination by allow and deny lists
+    pub fn is_allowed(&self, target: &Target) -> bool {
+        let host = target.host.trim_start_matches('[').trim_end_matches(']');
+        if self.deny.iter().any(|d| d.is_match(host, target.port)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|d| d.is_match(host, target.port))
+    }
+
+    /// Split absolute-form request target like `http://host:port/path` to destination
+    /// and origin-form target, returns [`None`] for origin-form request target
+    pub fn parse_absolute(request_target: &str) -> Option<(Target, String)> {
+        let uri = Uri::parse(request_target).ok()?;
+        if uri.form != UriForm::Absolute {
+            return None;
+        }
+        let authority = uri.authority.as_deref()?;
+        let scheme = uri.scheme.as_deref()?;
+        let target = Target::parse(&format!("{scheme}://{authority}")).ok()?;
+        Some((target, uri.origin()))
+    }
+}
@@ -0,0 +1,170 @@
+//! Module [`ForwardedHeaders`].
+//! Telling targets about clients by `X-Forwarded-*` and RFC 7239 `Forwarded` headers
+use crate::http::headers::Headers;
+use std::{
+    io::{Error, ErrorKind, Result},
+    net::IpAddr,
+};
+
+/// Network of IP addresses like `10.0.0.0/8` or `::1/128`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    pub address: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    /// Parse network like `10.0.0.0/8`, address without prefix is network of one address
+    pub fn parse(cidr: &str) -> Result<Self> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Failed parse network {}", cidr),
+            )
+        };
+        let (address, prefix) = match cidr.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (cidr, None),
+        };
+        let address: IpAddr = address.trim().parse().map_err(|_| invalid())?;
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { address, prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Configuration of forwarding headers.
+/// Values of headers from trusted proxies are extended, from other clients are replaced
+#[derive(Clone, Debug)]
+pub struct ForwardedHeaders {
+    /// Set `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`
+    pub x_forwarded: bool,
+    /// Set `Forwarded`
+    pub forwarded: bool,
+    /// Networks of proxies in front of this one
+    pub trusted: Vec<Cidr>,
+}
+
+impl Default for ForwardedHeaders {
+    fn default() -> Self {
+        ForwardedHeaders::new()
+    }
+}
+
+impl ForwardedHeaders {
+    /// Create config setting all headers without trusted proxies
+    pub fn new() -> Self {
+        Self {
+            x_forwarded: true,
+            forwarded: true,
+            trusted: vec![],
+        }
+    }
+
+    /// Switch `X-Forwarded-*` headers
+    pub fn with_x_forwarded(mut self, enabled: bool) -> Self {
+        self.x_forwarded = enabled;
+        self
+    }
+
+    /// Switch `Forwarded` header
+    pub fn with_forwarded(mut self, enabled: bool) -> Self {
+        self.forwarded = enabled;
+        self
+    }
+
+    /// Trust headers from proxies in network like `10.0.0.0/8`
+    pub fn with_trusted_proxy(mut self, cidr: &str) -> Result<Self> {
+        self.trusted.push(Cidr::parse(cidr)?);
+        Ok(self)
+    }
+
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// Set forwarding headers of request from client,
+    /// `proto` is `http` or `https` and `host` is `Host` of request before proxying
+    pub fn apply(
+        &self,
+        headers: &Headers,
+        client_ip: &IpAddr,
+        proto: &str,
+        host: Option<&str>,
+    ) -> Result<Headers> {
+        let trusted = self.is_trusted(client_ip);
+        let mut headers = headers.clone();
+        if self.x_forwarded {
+            let for_value = match ForwardedHeaders::get_values(&headers, "X-Forwarded-For") {
+                Some(prev) if trusted => format!("{}, {}", prev, client_ip),
+                _ => client_ip.to_string(),
+            };
+            headers = ForwardedHeaders::replace(&headers, "X-Forwarded-For", &for_value)?;
+            for (name, value) in [
+                ("X-Forwarded-Proto", Some(proto)),
+                ("X-Forwarded-Host", host),
+            ] {
+                let prev = headers.get_header(name);
+                headers = match (prev, value) {
+                    (Some(_), _) if trusted => headers,
+                    (_, Some(value)) => ForwardedHeaders::replace(&headers, name, value)?,
+                    (_, None) => headers.remove_header(name)?,
+                };
+            }
+        }
+        if self.forwarded {
+            let node = match client_ip {
+                IpAddr::V4(ip) => ip.to_string(),
+                IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+            };
+            let mut element = format!("for={};proto={}", node, proto);
+            if let Some(host) = host {
+                element = format!("{};host=\"{}\"", element, host.replace('"', ""));
+            }
+            let value = match ForwardedHeaders::get_values(&headers, "Forwarded") {
+                Some(prev) if trusted => format!("{}, {}", prev, element),
+                _ => element,
+            };
+            headers = ForwardedHeaders::replace(&headers, "Forwarded", &value)?;
+        }
+        Ok(headers)
+    }
+
+    /// Join values of all headers with name by comma
+    fn get_values(headers: &Headers, name: &str) -> Option<String> {
+        let map = headers.header_map();
+        let values: Vec<&str> = map.get_all(name).collect();
+        match values.is_empty() {
+            true => None,
+            false => Some(values.join(", ")),
+        }
+    }
+
+    /// Replace all headers with name by one header
+    fn replace(headers: &Headers, name: &str, value: &str) -> Result<Headers> {
+        headers.set_header(name, value)
+    }
+}
@@ -0,0 +1,144 @@
+//! Module [`HealthCheck`].
+//! Background probes of targets which eject failing ones from load balancing
+use crate::{
+    http::{
+        connector::{Connector, Target},
+        headers::Headers,
+        status::Status,
+        CRLF,
+    },
+    log::{Log, LogLevel},
+    prelude::constants::{
+        HEALTH_HEALTHY_THRESHOLD, HEALTH_INTERVAL, HEALTH_TIMEOUT, HEALTH_UNHEALTHY_THRESHOLD,
+        SHUTDOWN_POLL_INTERVAL,
+    },
+    shutdown::Shutdown,
+    upstream::{Balancer, UpstreamState},
+};
+use std::{
+    io::{Error, Result, Write},
+    sync::Arc,
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Way of checking target
+#[derive(Clone, Debug)]
+pub enum Probe {
+    /// Target accepts TCP connection
+    Tcp,
+    /// Target responds to `GET` request of `path` with `status`
+    Http { path: String, status: Status },
+}
+
+/// Configuration of active health checks
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    pub probe: Probe,
+    /// Pause between rounds of checks
+    pub interval: Duration,
+    /// Max time of one probe
+    pub timeout: Duration,
+    /// Count of failed probes in a row after which target is ejected
+    pub unhealthy_threshold: u32,
+    /// Count of succeeded probes in a row after which ejected target is admitted again
+    pub healthy_threshold: u32,
+}
+
+impl HealthCheck {
+    /// Create health check with default interval, timeout and thresholds
+    pub fn new(probe: Probe) -> Self {
+        Self {
+            probe,
+            interval: HEALTH_INTERVAL,
+            timeout: HEALTH_TIMEOUT,
+            unhealthy_threshold: HEALTH_UNHEALTHY_THRESHOLD,
+            healthy_threshold: HEALTH_HEALTHY_THRESHOLD,
+        }
+    }
+
+    /// Set pause between rounds of checks
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set max time of one probe
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set counts of failed and succeeded probes in a row which change health of target
+    pub fn with_thresholds(mut self, unhealthy: u32, healthy: u32) -> Self {
+        self.unhealthy_threshold = unhealthy;
+        self.healthy_threshold = healthy;
+        self
+    }
+
+    /// Run checks of balancers targets in background thread until shutdown
+    pub fn spawn(
+        self,
+        balancers: Vec<Arc<Balancer>>,
+        connector: Arc<Connector>,
+        log: Log,
+        shutdown: Shutdown,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            while !shutdown.is_triggered() {
+                for balancer in &balancers {
+                    for state in balancer.upstreams() {
+                        self.check(&state, &connector, &log);
+                    }
+                }
+                // Pause is interrupted by shutdown, so thread is joined without waiting for it
+                let started = Instant::now();
+                while !shutdown.is_triggered() && started.elapsed() < self.interval {
+                    sleep(SHUTDOWN_POLL_INTERVAL.min(self.interval));
+                }
+            }
+        })
+    }
+
+    /// Probe target once and update its health
+    pub fn check(&self, state: &UpstreamState, connector: &Connector, _log: &Log) {
+        const TAG: &str = "Health check";
+        let address = &state.upstream.address;
+
+        match self.probe(address, connector) {
+            Ok(()) => {
+                if state.report_success(self.healthy_threshold) {
+                    _log.println(LogLevel::Warn, TAG, "target is healthy", address);
+                }
+            }
+            Err(err) => {
+                _log.println(LogLevel::Info, TAG, format!("failed {}", address), &err);
+                if state.report_failure(self.unhealthy_threshold) {
+                    _log.println(LogLevel::Warn, TAG, "target is unhealthy", address);
+                }
+            }
+        }
+    }
+
+    fn probe(&self, address: &str, connector: &Connector) -> Result<()> {
+        let mut http = connector.connect(address, Some(self.timeout))?;
+        let (path, status) = match &self.probe {
+            Probe::Tcp => return Ok(()),
+            Probe::Http { path, status } => (path, status),
+        };
+
+        http.socket.set_read_timeout(Some(self.timeout))?;
+        http.socket.set_write_timeout(Some(self.timeout))?;
+        let host = Target::parse(address)?.host_header();
+        let req =
+            format!("GET {path} HTTP/1.1{CRLF}Host: {host}{CRLF}Connection: close{CRLF}{CRLF}");
+        http.write_all(req.as_bytes())?;
+
+        let heads = Headers::from(http.read_headers()?);
+        let res = heads.status()?;
+        if res.code != status.code {
+            return Err(Error::other(format!("Unexpected status {}", res)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! Module [`Body`].
+//! Framing of message body by `Content-Length` or `Transfer-Encoding: chunked`
+use super::{method::Method, request::Request};
+use std::io::Result;
+
+/// How the end of message body is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Body {
+    /// Message has no body
+    Empty,
+    /// Body has exact count of bytes
+    Length(usize),
+    /// Body is sent by chunks up to the last empty chunk and trailers
+    Chunked,
+    /// Body lasts until connection is closed
+    Close,
+}
+
+impl Body {
+    /// Get framing of request body
+    pub fn request(req: &Request) -> Self {
+        if req.chunked {
+            return Body::Chunked;
+        }
+        match usize::try_from(req.content_length) {
+            Ok(0) | Err(_) => Body::Empty,
+            Ok(len) => Body::Length(len),
+        }
+    }
+
+    /// Get framing of response body to request `req_client`
+    pub fn response(req_client: &Request, res: &Request) -> Result<Self> {
+        let status = res.headers.status()?;
+        if req_client.method == Method::Head
+            || (100..200).contains(&status.code)
+            || status.code == 204
+            || status.code == 304
+        {
+            return Ok(Body::Empty);
+        }
+        if res.chunked {
+            return Ok(Body::Chunked);
+        }
+        Ok(match res.headers.content_length() {
+            Some(len) => Body::Length(len),
+            None => Body::Close,
+        })
+    }
+
+    /// End of body is known without closing of connection
+    pub fn is_framed(&self) -> bool {
+        *self != Body::Close
+    }
+}
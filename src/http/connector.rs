@@ -0,0 +1,148 @@
+//! Module [`Connector`].
+//! Opening of connections to targets by address like `host:port` or `https://host:port`
+use super::Http;
+use std::{
+    io::{Error, ErrorKind, Result},
+    time::Duration,
+};
+
+/// Target address split to scheme, host and port
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Target {
+    /// Parse address like `host:port`, `http://host` or `https://host:port`,
+    /// port is taken by scheme if it is missing
+    pub fn parse(address: &str) -> Result<Self> {
+        let (tls, rest) = match address.split_once("://") {
+            Some((scheme, rest)) => match scheme.to_lowercase().as_str() {
+                "http" => (false, rest),
+                "https" => (true, rest),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Unsupported scheme of target {}", address),
+                    ))
+                }
+            },
+            None => (false, address),
+        };
+        let rest = rest.trim_end_matches('/');
+        let default_port = match tls {
+            true => 443,
+            false => 80,
+        };
+        let (host, port) = match rest.rsplit_once(':') {
+            // Colon inside of IPv6 address is not a port separator
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse::<u16>().map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Failed parse port of target {}: {}", address, e),
+                    )
+                })?,
+            ),
+            _ => (rest, default_port),
+        };
+        if host.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Host of target {} is empty", address),
+            ));
+        }
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// Address like `host:port` for TCP connection
+    pub fn authority(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Address of target for [`Connector::connect`], scheme is kept for TLS only
+    pub fn address(&self) -> String {
+        match self.tls {
+            true => format!("https://{}", self.authority()),
+            false => self.authority(),
+        }
+    }
+
+    /// Value of `Host` header, port is omitted if it is default for scheme
+    pub fn host_header(&self) -> String {
+        match (self.tls, self.port) {
+            (true, 443) | (false, 80) => self.host.clone(),
+            _ => self.authority(),
+        }
+    }
+}
+
+/// Creator of target connections, plain or TLS by scheme of target address
+#[derive(Debug, Default)]
+pub struct Connector {
+    #[cfg(feature = "tls")]
+    tls: Option<std::sync::Arc<rustls::ClientConfig>>,
+    /// SNI server name used instead of target host
+    #[cfg(feature = "tls")]
+    server_name: Option<String>,
+    /// Max time of connecting when timeout is not passed to [`Connector::connect`]
+    connect_timeout: Option<Duration>,
+}
+
+impl Connector {
+    /// Create connector with settings of TLS to targets
+    #[cfg(feature = "tls")]
+    pub fn with_tls(tls: &crate::tls::UpstreamTls) -> Result<Self> {
+        Ok(Self {
+            tls: Some(tls.client_config()?),
+            server_name: tls.server_name.clone(),
+            connect_timeout: None,
+        })
+    }
+
+    /// Wait for connection no longer than `timeout` by default
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Open connection to target, waiting no longer than `timeout` or default one if they are set
+    pub fn connect(&self, address: &str, timeout: Option<Duration>) -> Result<Http> {
+        let target = Target::parse(address)?;
+        let authority = target.authority();
+        #[allow(unused_mut)]
+        let mut http = match timeout.or(self.connect_timeout) {
+            Some(timeout) => Http::connect_timeout(&authority, timeout)?,
+            None => Http::connect(&authority)?,
+        };
+        if !target.tls {
+            return Ok(http);
+        }
+
+        #[cfg(feature = "tls")]
+        {
+            let config = match &self.tls {
+                Some(config) => std::sync::Arc::clone(config),
+                None => crate::tls::UpstreamTls::new().client_config()?,
+            };
+            let server_name = match &self.server_name {
+                Some(name) => name.as_str(),
+                None => target.host.trim_start_matches('[').trim_end_matches(']'),
+            };
+            http.connect_tls(config, server_name)?;
+            Ok(http)
+        }
+        #[cfg(not(feature = "tls"))]
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("Feature tls is required for target {}", address),
+        ))
+    }
+}
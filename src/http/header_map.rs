@@ -0,0 +1,224 @@
+//! Module [`HeaderMap`].
+//! Ordered headers with lookup by name in any case and many values per name
+use super::{headers::Header, parser::Fields, CRLF};
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    str,
+};
+
+/// Headers in order of message, names keep their original case
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<Entry>,
+}
+
+/// Header with value as received, so value which is not UTF-8 is sent as is
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    name: String,
+    value: String,
+    /// Bytes of value which is not UTF-8, they are dropped when value is changed
+    bytes: Option<Vec<u8>>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse header fields of head, lines which are not header fields are skipped
+    pub fn parse(raw: &[u8]) -> Self {
+        Self::from(&Fields::parse(raw).0)
+    }
+
+    /// Get first value of header
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str())
+    }
+
+    /// Get all values of header in order
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Set the only value of header, it takes place and case of name of the first old value,
+    /// new header is added to the end
+    pub fn insert(&mut self, name: &str, value: &str) -> Result<()> {
+        HeaderMap::check(name, value)?;
+        let mut found = false;
+        self.entries.retain_mut(|h| {
+            if !h.name.eq_ignore_ascii_case(name) {
+                return true;
+            }
+            if found {
+                return false;
+            }
+            found = true;
+            h.value = value.to_string();
+            h.bytes = None;
+            true
+        });
+        if !found {
+            self.push(name, value);
+        }
+        Ok(())
+    }
+
+    /// Add value to the end even if header exists
+    pub fn append(&mut self, name: &str, value: &str) -> Result<()> {
+        HeaderMap::check(name, value)?;
+        self.push(name, value);
+        Ok(())
+    }
+
+    /// Remove all values of header, returns the first of them
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+        self.entries.retain(|h| {
+            if !h.name.eq_ignore_ascii_case(name) {
+                return true;
+            }
+            removed.get_or_insert_with(|| h.value.clone());
+            false
+        });
+        removed
+    }
+
+    /// Change every value of header in place
+    pub fn update<F>(&mut self, name: &str, f: F) -> Result<()>
+    where
+        F: Fn(&str) -> String,
+    {
+        for h in self.entries.iter_mut() {
+            if h.name.eq_ignore_ascii_case(name) {
+                let value = f(&h.value);
+                HeaderMap::check(&h.name, &value)?;
+                if value != h.value {
+                    h.value = value;
+                    h.bytes = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pairs of name and value in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|h| (h.name.as_str(), h.value.as_str()))
+    }
+
+    /// Count of values
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Header lines with empty line at the end as they are sent,
+    /// values which are not UTF-8 keep their bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        for h in &self.entries {
+            buf.extend_from_slice(h.name.as_bytes());
+            buf.extend_from_slice(b": ");
+            match &h.bytes {
+                Some(bytes) => buf.extend_from_slice(bytes),
+                None => buf.extend_from_slice(h.value.as_bytes()),
+            }
+            buf.extend_from_slice(CRLF.as_bytes());
+        }
+        buf.extend_from_slice(CRLF.as_bytes());
+        buf
+    }
+
+    fn push(&mut self, name: &str, value: &str) {
+        self.entries.push(Entry {
+            name: name.to_string(),
+            value: value.to_string(),
+            bytes: None,
+        });
+    }
+
+    /// Name is a token and value has no line breaks, so header can't inject other lines
+    fn check(name: &str, value: &str) -> Result<()> {
+        if !super::parser::is_token(name.as_bytes()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid header name {:?}", name),
+            ));
+        }
+        if value.contains(['\r', '\n', '\0']) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid value of header {}", name),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for HeaderMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for h in &self.entries {
+            write!(f, "{}: {}{CRLF}", h.name, h.value)?;
+        }
+        f.write_str(CRLF)
+    }
+}
+
+impl From<&Fields<'_>> for HeaderMap {
+    fn from(fields: &Fields) -> Self {
+        Self {
+            entries: fields
+                .iter()
+                .map(|f| Entry {
+                    name: f.name.to_string(),
+                    value: f.value_str().to_string(),
+                    bytes: str::from_utf8(f.value).is_err().then(|| f.value.to_vec()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Vec<Header>> for HeaderMap {
+    fn from(list: Vec<Header>) -> Self {
+        Self {
+            entries: list
+                .into_iter()
+                .map(|h| Entry {
+                    name: h.name,
+                    value: h.value,
+                    bytes: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<HeaderMap> for Vec<Header> {
+    fn from(map: HeaderMap) -> Self {
+        map.entries
+            .into_iter()
+            .map(|h| Header {
+                name: h.name,
+                value: h.value,
+            })
+            .collect()
+    }
+}
@@ -1,4 +1,4 @@
-///! Module [`Header`]
+//! Module [`Header`]
 use crate::http::CRLF;
 #[cfg(feature = "napi")]
 use napi_derive::napi;
@@ -10,30 +10,101 @@ use std::{
     str,
 };
 
-use super::status::Status;
+use super::{
+    header_map::HeaderMap, method::Method, parser::Head, status::Status, version::Version,
+};
 
 /// HTTP header
 #[cfg_attr(feature = "napi", napi(object))]
-#[derive(Debug, Serialize, Clone, Deserialize)]
+#[derive(Debug, Serialize, Clone, Deserialize, PartialEq, Eq)]
 pub struct Header {
     pub name: String,
     pub value: String,
 }
 
-/// HTTP headers
-#[cfg_attr(feature = "napi", napi(object))]
+/// HTTP headers, bytes of head are built from request line or status line and [`HeaderMap`],
+/// so they are always changed together, head is parsed once when headers are created
 #[derive(Debug, Serialize, Clone, Deserialize)]
+#[serde(into = "HeadersFields", from = "HeadersFields")]
 pub struct Headers {
-    pub raw: String,
-    pub list: Vec<Header>,
+    raw: Vec<u8>,
+    map: HeaderMap,
+    head: Parsed,
+}
+
+/// Start line and framing of head
+#[derive(Debug, Clone, Default)]
+struct Parsed {
+    method: Option<Method>,
+    target: Option<String>,
+    status: Option<Status>,
+    version: Option<Version>,
+    content_length: Option<usize>,
+    chunked: bool,
+    keep_alive: bool,
+    upgrade: bool,
+}
+
+/// Serialized form of [`Headers`], list is derived from raw text when it is parsed
+#[derive(Serialize, Deserialize)]
+struct HeadersFields {
+    raw: String,
+    #[serde(default)]
+    list: Vec<Header>,
+}
+
+impl From<Headers> for HeadersFields {
+    fn from(headers: Headers) -> Self {
+        Self {
+            list: headers.list(),
+            raw: headers.into(),
+        }
+    }
+}
+
+impl From<HeadersFields> for Headers {
+    fn from(fields: HeadersFields) -> Self {
+        Headers::from_string(fields.raw)
+    }
+}
+
+impl From<String> for Headers {
+    fn from(raw: String) -> Self {
+        Headers::from_string(raw)
+    }
 }
 
+impl From<Vec<u8>> for Headers {
+    fn from(raw: Vec<u8>) -> Self {
+        Headers::parse(raw)
+    }
+}
+
+/// Text of head, bytes which are not UTF-8 are replaced
+impl From<Headers> for String {
+    fn from(headers: Headers) -> Self {
+        match String::from_utf8(headers.raw) {
+            Ok(raw) => raw,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "napi")]
+super::napi_string!(Headers);
+
 impl fmt::Display for Header {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}: {}", self.name, self.value)
     }
 }
 
+impl Default for Headers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Headers {
     pub fn new() -> Self {
         Headers::from_string("".to_string())
@@ -42,13 +113,7 @@ impl Headers {
     // Create request headers
     pub fn new_request(prefix: &str, list: Vec<Header>) -> Self {
         let postfix = Headers::to_string(list);
-        let raw = format!(
-            "{}{CRLF}{postfix}",
-            Regex::new(r"\s*$")
-                .unwrap()
-                .replace_all(prefix, "")
-                .to_string()
-        );
+        let raw = format!("{}{CRLF}{postfix}", prefix.trim_end());
         Headers::from_string(raw)
     }
 
@@ -60,275 +125,164 @@ impl Headers {
         Headers::from_string(raw)
     }
 
-    /// Parse headers
+    /// Parse headers, lines which are not header fields are skipped
     pub fn from_string(raw: String) -> Self {
-        let mut res: Headers = Headers {
-            raw: raw.clone(),
-            list: vec![],
-        };
-        let heads = raw.split(CRLF);
-        for h in heads {
-            let reg_name = Regex::new(r"^.+: ").unwrap();
-            let capts_name = reg_name.captures(h);
-            if let None = capts_name {
-                continue;
-            }
-            let capts_name = capts_name.unwrap();
-            let name = capts_name
-                .get(0)
-                .unwrap()
-                .as_str()
-                .to_string()
-                .replace(": ", "");
-
-            let reg_value = Regex::new(r": *.*$").unwrap();
-            let capts_value = reg_value.captures(h);
-            if let None = capts_value {
-                res.list.push(Header {
-                    name,
-                    value: "".to_string(),
-                });
-                continue;
-            }
-            let capts_value = capts_value.unwrap();
-            let value = capts_value
-                .get(0)
-                .unwrap()
-                .as_str()
-                .to_string()
-                .replace(": ", "");
-            res.list.push(Header { name, value });
-        }
-        res
+        Headers::parse(raw.into_bytes())
     }
 
     /// Create string of headers from list
     pub fn to_string(list: Vec<Header>) -> String {
-        let mut result = "".to_string();
-        for h in list {
-            result = format!("{result}{}: {}{CRLF}", h.name, h.value);
-        }
-        result = format!("{result}{CRLF}");
-        result
+        HeaderMap::from(list).to_string()
     }
 
-    /// Create headers from bytes
-    pub fn from_bytes(heads: &Vec<u8>) -> Result<Self> {
-        let res = str::from_utf8(heads);
-        if let Err(err) = res {
-            return Err(Error::new(ErrorKind::InvalidInput, err));
-        }
-        let res = res.unwrap().to_string();
-        Ok(Headers::from_string(res))
-    }
-
-    fn change_header(&self, name: &str, value: &str) -> (Vec<Header>, bool) {
-        let mut check = false;
-        let mut new_list: Vec<Header> = vec![];
-        for h in self.list.clone() {
-            if name.to_lowercase() == h.name.as_str().to_lowercase() {
-                new_list.push(Header {
-                    name: name.to_string(),
-                    value: value.to_string(),
-                });
-                check = true;
-            } else {
-                new_list.push(Header {
-                    name: h.name.to_string(),
-                    value: h.value.to_string(),
-                });
-            }
-        }
-        (new_list, check)
+    /// Create headers from bytes, values which are not UTF-8 are kept as is
+    pub fn from_bytes(heads: &[u8]) -> Self {
+        Headers::parse(heads.to_vec())
     }
 
-    /// Set new header or change old one
-    pub fn set_header(&self, name: &str, value: &str) -> Result<Self> {
-        let (mut new_list, check) = self.change_header(name, value);
-        if !check {
-            new_list.push(Header {
-                name: name.to_string(),
-                value: value.to_string(),
-            });
-        }
+    /// Bytes of head with request line or status line and empty line at the end
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
 
-        let new_h = match self.is_response() {
-            true => {
-                let status = Headers::get_status(&self.raw)?;
-                Headers::new_response(&status, new_list)
-            }
-            false => {
-                let prefix = Headers::get_headers_prefix(&self.raw)?;
-                Headers::new_request(prefix.as_str(), new_list)
-            }
-        };
+    /// Headers in order of head
+    pub fn list(&self) -> Vec<Header> {
+        self.map.clone().into()
+    }
 
-        Ok(Headers {
-            list: new_h.list,
-            raw: new_h.raw,
-        })
+    /// Get value of first header with name in any case
+    pub fn get_header(&self, name: &str) -> Option<String> {
+        self.map.get(name).map(str::to_string)
     }
 
-    /// Parse content length from request headers
-    pub fn get_content_length(raw: &String) -> Option<u32> {
-        let raw = raw.to_lowercase();
-        let low = Regex::new(r"content-length: *\d+").unwrap().captures(&raw);
+    /// Get headers for lookup and changes
+    pub fn header_map(&self) -> HeaderMap {
+        self.map.clone()
+    }
 
-        let mut check: Option<&str> = None;
-        if let Some(v) = low {
-            let low = v.get(0).unwrap();
-            check = Some(low.as_str());
-        }
+    /// Rebuild headers with same request line or status line and new headers,
+    /// so raw bytes and map are always changed together
+    pub fn with_header_map(&self, map: HeaderMap) -> Result<Self> {
+        let mut raw = self.prefix()?.to_vec();
+        raw.extend_from_slice(CRLF.as_bytes());
+        raw.extend_from_slice(&map.to_bytes());
+        Ok(Headers::parse(raw))
+    }
 
-        if let None = check {
-            return None;
-        }
+    /// Rebuild headers with new request line or status line and same headers
+    pub fn with_prefix(&self, prefix: &str) -> Self {
+        let mut raw = format!("{}{CRLF}", prefix.trim_end()).into_bytes();
+        raw.extend_from_slice(&self.map.to_bytes());
+        Headers::parse(raw)
+    }
 
-        let cont_len = check.unwrap();
+    /// Set the only value of header, case and place of old header are kept
+    pub fn set_header(&self, name: &str, value: &str) -> Result<Self> {
+        let mut map = self.header_map();
+        map.insert(name, value)?;
+        self.with_header_map(map)
+    }
 
-        let num = Regex::new(r"\d+").unwrap().captures(cont_len);
-        if let None = num {
-            return None;
-        }
-        let capts = num.unwrap();
-        let num = capts.get(0);
-        let num_str = num.unwrap().as_str();
-        let num = num_str.parse::<u32>();
-        if let Err(e) = num {
-            println!("Failed parse content lenght from str: {}: {}", num_str, e);
-            return None;
-        }
-        Some(num.unwrap())
+    /// Add header even if header with same name exists
+    pub fn add_header(&self, name: &str, value: &str) -> Result<Self> {
+        let mut map = self.header_map();
+        map.append(name, value)?;
+        self.with_header_map(map)
     }
 
-    /// Get url from raw headers
-    pub fn get_url(raw: &String) -> String {
-        let reg = Regex::new(r"\/[a-zA-Z0-9_\-\/]*").unwrap();
-        let capts = reg.captures(raw.as_str());
-        if let None = capts {
-            return "/".to_string();
-        }
-        let capts = capts.unwrap();
-        let res = capts.get(0).unwrap().as_str();
-        res.to_string()
+    /// Replace matches of regex in values of all headers with name in any case
+    pub fn replace_header(&self, name: &str, regex: &Regex, replacement: &str) -> Result<Self> {
+        let mut map = self.header_map();
+        map.update(name, |value| {
+            regex.replace_all(value, replacement).to_string()
+        })?;
+        self.with_header_map(map)
     }
 
-    /// Get query string from raw headers
-    pub fn get_query(raw: &String) -> String {
-        let reg = Regex::new(r"\?[a-zA-Z0-9_\-&=\.]*").unwrap();
-        let capts = reg.captures(raw.as_str());
-        if let None = capts {
-            return "".to_string();
-        }
-        let capts = capts.unwrap();
-        let res = capts.get(0).unwrap().as_str();
-        res.to_string()
+    /// Remove all headers with name in any case
+    pub fn remove_header(&self, name: &str) -> Result<Self> {
+        let mut map = self.header_map();
+        map.remove(name);
+        self.with_header_map(map)
     }
 
-    /// Get request chunked
-    pub fn get_chunked(raw: &String) -> bool {
-        let reg = Regex::new(r"transfer-encoding: *chunked").unwrap();
-        let raw = raw.to_lowercase();
-        let capts = reg.captures(raw.as_str());
+    /// Get method from request line
+    pub fn method(&self) -> Option<&Method> {
+        self.head.method.as_ref()
+    }
 
-        if let None = capts {
-            return false;
-        }
-        return true;
+    /// Get request target from request line as is, like `/path?query`
+    pub fn target(&self) -> Option<&str> {
+        self.head.target.as_deref()
     }
 
-    // Get protocol from raw headers
-    pub fn get_protocol(raw: &String) -> String {
-        let reg = Regex::new(r"HTTPS?\/\d+\.\d+").unwrap();
-        let capts = reg.captures(raw.as_str());
-        if let None = capts {
-            return "OPTIONS".to_string();
-        }
-        let capts = capts.unwrap();
-        let protocol = capts.get(0).unwrap().as_str();
-        protocol.to_string()
+    /// Get status of response from status line
+    pub fn status(&self) -> Result<&Status> {
+        self.head
+            .status
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Status line didn't find"))
     }
 
-    // Get request prefix
-    fn get_status(raw: &String) -> Result<Status> {
-        let reg = Regex::new(format!(r".+{CRLF}").as_str()).unwrap();
-        let capts = reg.captures(raw.as_str());
-        if let None = capts {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "Headers prefix didn't find",
-            ));
-        }
-        let capts = capts.unwrap();
-        let result = capts.get(0).unwrap().as_str();
-
-        let result = Regex::new(format!(r"^HTTPS?\/\d+\.\d+ ").as_str())
-            .unwrap()
-            .replace_all(result, "")
-            .to_string();
-
-        let reg = Regex::new(format!(r"^\d+").as_str()).unwrap();
-        let capts = reg.captures(result.as_str());
-        if let None = capts {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "Headers prefix didn't find",
-            ));
-        }
-        let capts = capts.unwrap();
-        let code = capts.get(0).unwrap().as_str().parse::<u16>();
-        if let Err(err) = code {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("Failed parse status code {:?}", err),
-            ));
-        }
-        let code = code.unwrap();
+    /// Get protocol version from request line or status line
+    pub fn version(&self) -> Option<Version> {
+        self.head.version
+    }
+
+    /// Get value of `Content-Length` if it has digits only
+    pub fn content_length(&self) -> Option<usize> {
+        self.head.content_length
+    }
+
+    /// Body is chunked, chunked must be the last coding
+    pub fn is_chunked(&self) -> bool {
+        self.head.chunked
+    }
 
-        let text = Regex::new(format!(r"^\d+ ").as_str())
-            .unwrap()
-            .replace_all(result.as_str(), "")
-            .to_string();
+    /// Check persistent connection by protocol version and `Connection` header
+    pub fn keep_alive(&self) -> bool {
+        self.head.keep_alive
+    }
 
-        Ok(Status { code, text })
+    /// Check request of protocol change by `Connection: upgrade` and `Upgrade` headers
+    pub fn upgrade(&self) -> bool {
+        self.head.upgrade
     }
 
-    /// Get method from raw headers
-    pub fn get_method(raw: &String) -> String {
-        let reg = Regex::new(r"\w+").unwrap();
-        let capts = reg.captures(raw.as_str());
-        if let None = capts {
-            return "OPTIONS".to_string();
+    /// Parse start line and header fields of head once
+    fn parse(raw: Vec<u8>) -> Self {
+        let head = Head::parse(&raw);
+        let line = head.request_line();
+        let parsed = Parsed {
+            method: line.map(|l| Method::from(l.method)),
+            target: line.map(|l| l.target.to_string()),
+            status: head.status_line().map(|l| Status {
+                code: l.code,
+                text: l.reason.to_string(),
+            }),
+            version: head.start.version().and_then(|v| v.parse().ok()),
+            content_length: head.fields.content_length(),
+            chunked: head.fields.is_chunked(),
+            keep_alive: head.keep_alive(),
+            upgrade: head.upgrade(),
+        };
+        let map = HeaderMap::from(&head.fields);
+        Headers {
+            raw,
+            map,
+            head: parsed,
         }
-        let capts = capts.unwrap();
-        let method = capts.get(0).unwrap().as_str();
-        method.to_string()
     }
 
-    // Get request prefix
-    fn get_headers_prefix(raw: &String) -> Result<String> {
-        let reg = Regex::new(format!(r".+{CRLF}").as_str()).unwrap();
-        let capts = reg.captures(raw.as_str());
-        if let None = capts {
-            return Err(Error::new(
+    /// Get request line or status line
+    fn prefix(&self) -> Result<&[u8]> {
+        let line = self.raw.split(|b| *b == b'\n').next().unwrap_or_default();
+        match line.strip_suffix(b"\r").unwrap_or(line) {
+            line if !line.is_empty() => Ok(line),
+            _ => Err(Error::new(
                 ErrorKind::InvalidInput,
                 "Headers prefix didn't find",
-            ));
-        }
-        let capts = capts.unwrap();
-        let result = capts.get(0).unwrap().as_str();
-        let result = Regex::new(format!(r"{CRLF}+$").as_str())
-            .unwrap()
-            .replace_all(result, "");
-        Ok(result.to_string())
-    }
-
-    fn is_response(&self) -> bool {
-        let reg = Regex::new(r"^HTTPS?/\d+\.\d+").unwrap();
-        let capts = reg.captures(self.raw.as_str());
-        if let None = capts {
-            return false;
+            )),
         }
-        true
     }
 }
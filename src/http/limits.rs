@@ -0,0 +1,169 @@
+//! Module [`Limits`].
+//! Limits of request size and strict checks of request framing against smuggling
+use super::parser::{is_token, Head};
+use crate::prelude::constants::{LIMIT_HEADERS, LIMIT_HEADER_SIZE, LIMIT_REQUEST_LINE};
+use std::{
+    error, fmt,
+    io::{Error, ErrorKind, Result},
+};
+
+/// Limits of client requests, exceeded ones are answered with `413`, `414` or `431`
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Max size of request line and headers in bytes
+    pub header_size: usize,
+    /// Max count of headers
+    pub headers: usize,
+    /// Max size of request line in bytes
+    pub request_line: usize,
+    /// Max size of request body in bytes, it is not limited by default
+    pub body_size: Option<usize>,
+}
+
+/// Request is rejected with status sent to client
+#[derive(Debug)]
+pub struct LimitError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl error::Error for LimitError {}
+
+impl LimitError {
+    /// Create IO error with status for client
+    pub fn reject(status: u16, message: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            LimitError {
+                status,
+                message: message.to_string(),
+            },
+        )
+    }
+
+    /// Get status for client if error is caused by rejected request
+    pub fn status(err: &Error) -> Option<u16> {
+        err.get_ref()
+            .and_then(|e| e.downcast_ref::<LimitError>())
+            .map(|e| e.status)
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits::new()
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self {
+            header_size: LIMIT_HEADER_SIZE,
+            headers: LIMIT_HEADERS,
+            request_line: LIMIT_REQUEST_LINE,
+            body_size: None,
+        }
+    }
+
+    pub fn with_header_size(mut self, size: usize) -> Self {
+        self.header_size = size;
+        self
+    }
+
+    pub fn with_headers(mut self, count: usize) -> Self {
+        self.headers = count;
+        self
+    }
+
+    pub fn with_request_line(mut self, size: usize) -> Self {
+        self.request_line = size;
+        self
+    }
+
+    pub fn with_body_size(mut self, size: usize) -> Self {
+        self.body_size = Some(size);
+        self
+    }
+
+    /// Check request line and headers: sizes, obsolete line folding, malformed request line
+    /// and header lines, bare CR and NUL, invalid or duplicate `Content-Length`
+    /// and its combination with `Transfer-Encoding`
+    pub fn check(&self, raw: &str) -> Result<()> {
+        if raw.len() > self.header_size {
+            return Err(LimitError::reject(431, "Request headers are too large"));
+        }
+        let mut lines = raw
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line));
+        if lines.next().unwrap_or("").len() > self.request_line {
+            return Err(LimitError::reject(414, "Request line is too long"));
+        }
+
+        let mut count = 0;
+        let mut content_length = vec![];
+        let mut transfer_encoding = vec![];
+        for line in lines.take_while(|line| !line.is_empty()) {
+            count += 1;
+            if count > self.headers {
+                return Err(LimitError::reject(431, "Too many request headers"));
+            }
+            if line.starts_with([' ', '\t']) {
+                return Err(LimitError::reject(400, "Obsolete line folding"));
+            }
+            let name = match line.split_once(':') {
+                Some((name, _)) if is_token(name.as_bytes()) => name,
+                _ => return Err(LimitError::reject(400, "Invalid header line")),
+            };
+            let value = line[name.len() + 1..].trim();
+            match name.to_lowercase().as_str() {
+                "content-length" => content_length.push(value),
+                "transfer-encoding" => transfer_encoding.push(value),
+                _ => {}
+            }
+        }
+
+        // Start line must be request line, header lines can't have bare CR or NUL
+        let head = Head::parse_strict(raw.as_bytes())
+            .map_err(|e| LimitError::reject(400, &e.to_string()))?;
+        if head.request_line().is_none() {
+            return Err(LimitError::reject(400, "Invalid request line"));
+        }
+
+        if content_length.len() > 1 {
+            return Err(LimitError::reject(400, "Duplicate Content-Length"));
+        }
+        if !transfer_encoding.is_empty() {
+            if !content_length.is_empty() {
+                return Err(LimitError::reject(
+                    400,
+                    "Content-Length with Transfer-Encoding",
+                ));
+            }
+            // Chunked must be the last coding, otherwise request body has no end
+            let codings = transfer_encoding.join(",");
+            let last = codings.rsplit(',').next().unwrap_or("").trim();
+            if !last.eq_ignore_ascii_case("chunked") {
+                return Err(LimitError::reject(400, "Invalid Transfer-Encoding"));
+            }
+        }
+        if let Some(value) = content_length.first() {
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(LimitError::reject(400, "Invalid Content-Length"));
+            }
+            let too_large = match value.parse::<usize>() {
+                Ok(len) => self.body_size.is_some_and(|max| len > max),
+                Err(_) => true,
+            };
+            if too_large {
+                return Err(LimitError::reject(413, "Request body is too large"));
+            }
+        }
+        Ok(())
+    }
+}
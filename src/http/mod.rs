@@ -1,17 +1,72 @@
+//! Module [`Http`].
+//! The minimum set of methods to work through [`TcpStream`].
+/// Conversion of type to and from JavaScript string by its string form
+#[cfg(feature = "napi")]
+macro_rules! napi_string {
+    ($type:ty) => {
+        impl napi::bindgen_prelude::TypeName for $type {
+            fn type_name() -> &'static str {
+                "String"
+            }
+
+            fn value_type() -> napi::ValueType {
+                napi::ValueType::String
+            }
+        }
+
+        impl napi::bindgen_prelude::ToNapiValue for $type {
+            unsafe fn to_napi_value(
+                env: napi::sys::napi_env,
+                val: Self,
+            ) -> napi::Result<napi::sys::napi_value> {
+                <String as napi::bindgen_prelude::ToNapiValue>::to_napi_value(env, val.into())
+            }
+        }
+
+        impl napi::bindgen_prelude::FromNapiValue for $type {
+            unsafe fn from_napi_value(
+                env: napi::sys::napi_env,
+                napi_val: napi::sys::napi_value,
+            ) -> napi::Result<Self> {
+                let value = <String as napi::bindgen_prelude::FromNapiValue>::from_napi_value(
+                    env, napi_val,
+                )?;
+                Self::try_from(value).map_err(|e| napi::Error::from_reason(e.to_string()))
+            }
+        }
+    };
+}
+#[cfg(feature = "napi")]
+use napi_string;
+
+pub mod body;
+pub mod connector;
+pub mod header_map;
 pub mod headers;
+pub mod limits;
+pub mod method;
+pub mod parser;
+pub mod pool;
+pub mod proxy_protocol;
 pub mod request;
 pub mod status;
-use self::request::Request;
+pub mod timeouts;
+pub mod uri;
+pub mod version;
+use self::{
+    body::Body,
+    headers::{Header, Headers},
+    limits::LimitError,
+    proxy_protocol::ProxyHeader,
+    request::Request,
+};
 
-use super::log::Log;
 use super::prelude::constants::*;
-///! Module [`Http`].
-///! The minimum set of methods to work through [`TcpStream`].
-use regex::Regex;
 use std::{
     io::{Error, ErrorKind, Read, Result, Write},
-    net::TcpStream,
+    net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
     str,
+    time::{Duration, Instant},
 };
 
 /// End of line constant ([`\r\n`])
@@ -20,6 +75,11 @@ pub const CRLF: &str = "\r\n";
 #[derive(Debug)]
 pub struct Http {
     pub socket: TcpStream,
+    /// Addresses of client received by PROXY protocol
+    proxied: Option<ProxyHeader>,
+    /// TLS session over socket
+    #[cfg(feature = "tls")]
+    tls: Option<Box<rustls::Connection>>,
 }
 
 impl Http {
@@ -29,91 +89,520 @@ impl Http {
         Ok(Http::from(socket))
     }
 
+    /// Create [`Http`] with new TCP connection which is waited no longer than `timeout`
+    pub fn connect_timeout(address: &str, timeout: Duration) -> Result<Http> {
+        let mut last_err = Error::new(
+            ErrorKind::InvalidInput,
+            format!("Address {} is not resolved", address),
+        );
+        for addr in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(socket) => return Ok(Http::from(socket)),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
     /// Create [`Http`] from exists socket
     pub fn from(socket: TcpStream) -> Http {
-        Http { socket }
+        Http {
+            socket,
+            proxied: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Data or end of connection can be read without blocking,
+    /// it is buffered by TLS session or received by socket
+    pub fn is_readable(&mut self) -> bool {
+        #[cfg(feature = "tls")]
+        if let Some(conn) = self.tls.as_mut() {
+            let buffered = conn
+                .process_new_packets()
+                .map(|state| state.plaintext_bytes_to_read() > 0);
+            if buffered.unwrap_or(true) {
+                return true;
+            }
+        }
+        if self.socket.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let res = self.socket.peek(&mut [0; 1]);
+        let _ = self.socket.set_nonblocking(false);
+        !matches!(res, Err(e) if e.kind() == ErrorKind::WouldBlock)
+    }
+
+    /// Read PROXY protocol header of v1 or v2 before any other data of connection
+    pub fn accept_proxy_protocol(&mut self) -> Result<()> {
+        self.proxied = ProxyHeader::read(self)?;
+        Ok(())
+    }
+
+    /// Address of peer, it is taken from PROXY protocol header if it is received
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        match &self.proxied {
+            Some(header) => Ok(header.source),
+            None => self.socket.peer_addr(),
+        }
+    }
+
+    /// Address which peer is connected to, it is taken from PROXY protocol header if it is received
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match &self.proxied {
+            Some(header) => Ok(header.destination),
+            None => self.socket.local_addr(),
+        }
+    }
+
+    /// Start TLS session of server side, handshake is done on first read or write
+    #[cfg(feature = "tls")]
+    pub fn accept_tls(&mut self, config: std::sync::Arc<rustls::ServerConfig>) -> Result<()> {
+        let conn = rustls::ServerConnection::new(config).map_err(Error::other)?;
+        self.tls = Some(Box::new(conn.into()));
+        Ok(())
+    }
+
+    /// Start TLS session of client side, handshake is done on first read or write
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(
+        &mut self,
+        config: std::sync::Arc<rustls::ClientConfig>,
+        server_name: &str,
+    ) -> Result<()> {
+        let name = rustls::pki_types::ServerName::try_from(server_name.to_string())
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let conn = rustls::ClientConnection::new(config, name).map_err(Error::other)?;
+        self.tls = Some(Box::new(conn.into()));
+        Ok(())
+    }
+
+    /// Notify peer about closing of TLS session, plain connection is left as is
+    pub fn close_notify(&mut self) -> Result<()> {
+        #[cfg(feature = "tls")]
+        if let Some(conn) = self.tls.as_mut() {
+            conn.send_close_notify();
+            while conn.wants_write() {
+                conn.write_tls(&mut self.socket)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Connection is protected with TLS
+    pub fn is_tls(&self) -> bool {
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return true;
+        }
+        false
     }
 
     /// Write end of request
     pub fn set_zero_byte(&mut self) -> Result<usize> {
-        self.write(format!("0{CRLF}{CRLF}").as_bytes())
+        let end = format!("0{CRLF}{CRLF}");
+        self.write_all(end.as_bytes())?;
+        Ok(end.len())
     }
 
-    /// Read request body
+    /// Read whole request body by its framing, chunked body is decoded and trailers are skipped
     pub fn read_body(&mut self, req: &Request) -> Result<Vec<u8>> {
-        let mut buf: Vec<u8> = vec![];
-        if req.content_length == 0 && !req.chunked {
-            return Ok(vec![]);
+        self.read_framed(&Body::request(req))
+    }
+
+    /// Read whole body of request or response by framing
+    pub fn read_framed(&mut self, body: &Body) -> Result<Vec<u8>> {
+        match body {
+            Body::Chunked => Ok(self.read_chunked()?.0),
+            Body::Length(len) => {
+                let mut buf = vec![0; *len];
+                self.read_exact(&mut buf)?;
+                Ok(buf)
+            }
+            Body::Close => self.read_to_close(),
+            Body::Empty => Ok(vec![]),
         }
-        loop {
-            let mut chunk = [0; super::CHUNK_SIZE];
-            self.read(&mut chunk)?;
-            let mut exit = false;
-            'b: for ch in chunk {
-                if ch == 0 {
-                    exit = true;
-                    break 'b;
+    }
+
+    /// Read whole body by framing while it is not longer than `max_size`, returns data with
+    /// count of bytes left unread otherwise. Body with known length is not read then,
+    /// chunked body is read up to data of chunk exceeding limit and left count is size of chunk.
+    /// Rest of body is forwarded by [`Http::forward_rest`]
+    pub fn read_framed_limited(
+        &mut self,
+        body: &Body,
+        max_size: usize,
+    ) -> Result<(Vec<u8>, Option<usize>)> {
+        match body {
+            Body::Length(len) if *len > max_size => Ok((vec![], Some(*len))),
+            Body::Close => Ok((vec![], Some(0))),
+            Body::Chunked => match self.read_chunks(max_size)? {
+                (buf, Some(left)) => Ok((buf, Some(left))),
+                (buf, None) => {
+                    self.read_trailers()?;
+                    Ok((buf, None))
                 }
-                buf.push(ch);
+            },
+            _ => Ok((self.read_framed(body)?, None)),
+        }
+    }
+
+    /// Write part of body read by [`Http::read_framed_limited`] and forward rest of it from `from`
+    /// with the same framing
+    pub fn forward_rest(
+        &mut self,
+        from: &mut Self,
+        body: &Body,
+        data: &[u8],
+        left: usize,
+    ) -> Result<usize> {
+        match body {
+            Body::Chunked => {
+                if !data.is_empty() {
+                    write!(self, "{:x}{CRLF}", data.len())?;
+                    self.write_all(data)?;
+                    self.write_all(CRLF.as_bytes())?;
+                }
+                write!(self, "{:x}{CRLF}", left)?;
+                let size = self.forward_length(from, left)?;
+                from.read_chunk_end()?;
+                self.write_all(CRLF.as_bytes())?;
+                Ok(data.len() + size + self.forward_chunked(from)?)
+            }
+            Body::Close => {
+                self.write_all(data)?;
+                Ok(data.len() + self.tunnel(from)?)
+            }
+            _ => {
+                self.write_all(data)?;
+                Ok(data.len() + self.forward_length(from, left)?)
+            }
+        }
+    }
+
+    /// Read and decode chunked body, returns data and trailers
+    pub fn read_chunked(&mut self) -> Result<(Vec<u8>, Vec<Header>)> {
+        let (buf, _) = self.read_chunks(usize::MAX)?;
+        Ok((buf, self.read_trailers()?))
+    }
+
+    /// Read chunks up to the last one, chunk exceeding `max_size` is not read
+    /// and its size is returned
+    fn read_chunks(&mut self, max_size: usize) -> Result<(Vec<u8>, Option<usize>)> {
+        let mut buf: Vec<u8> = vec![];
+        loop {
+            let line = self.read_line()?;
+            let chunk_size = Http::parse_chunk_size(&line)?;
+            if chunk_size == 0 {
+                return Ok((buf, None));
             }
-            if exit == true {
+            if chunk_size > max_size - buf.len() {
+                return Ok((buf, Some(chunk_size)));
+            }
+            // Buffer grows with received data, not by size of chunk
+            let len = self.take(chunk_size as u64).read_to_end(&mut buf)?;
+            if len < chunk_size {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Connection closed before end of chunk",
+                ));
+            }
+            self.read_chunk_end()?;
+        }
+    }
+
+    /// Read trailers after the last chunk
+    fn read_trailers(&mut self) -> Result<Vec<Header>> {
+        let mut trailers = vec![];
+        loop {
+            let line = self.read_line()?;
+            if line == CRLF.as_bytes() || line == b"\n" {
                 break;
             }
+            let trailer = Headers::from(line);
+            trailers.extend(trailer.list());
         }
+        Ok(trailers)
+    }
+
+    /// Read all data until connection is closed
+    pub fn read_to_close(&mut self) -> Result<Vec<u8>> {
+        let mut buf: Vec<u8> = vec![];
+        self.read_to_end(&mut buf)?;
         Ok(buf)
     }
 
     /// Body to string
     pub fn body_to_string(&mut self, body: Vec<u8>) -> Result<String> {
-        let res = str::from_utf8(&body);
-        if let Err(err) = res {
-            println!("{}", err);
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Failed to parse body to string",
-            ));
+        match String::from_utf8(body) {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                println!("{}", err);
+                Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Failed to parse body to string",
+                ))
+            }
         }
-        let result = res.unwrap();
-        let rec = Regex::new(r"\d*\r\n(0$)?").unwrap().replace_all(result, "");
-        Ok(rec.to_string())
     }
 
-    /// Client - Target tunnel core
-    pub fn tunnel(&mut self, http: &mut Self, _log: &Log) -> Result<usize> {
+    /// Stream body from `from` to self by its framing without buffering of the whole body
+    pub fn forward_body(&mut self, from: &mut Self, body: &Body) -> Result<usize> {
+        match body {
+            Body::Empty => Ok(0),
+            Body::Length(len) => self.forward_length(from, *len),
+            Body::Chunked => self.forward_chunked(from),
+            Body::Close => self.tunnel(from),
+        }
+    }
+
+    /// Client - Target tunnel core, copies data from `http` until it closes connection
+    pub fn tunnel(&mut self, http: &mut Self) -> Result<usize> {
         let mut size: usize = 0;
+        let mut b = [0; CHUNK_SIZE];
         loop {
-            let mut b = [0; CHUNK_SIZE];
-            http.read(&mut b)?;
-            let mut buf = vec![];
-            b.map(|_b| {
-                if _b != 0 {
-                    buf.push(_b);
-                    return true;
+            let len = http.read(&mut b)?;
+            if len == 0 {
+                break;
+            }
+            size += len;
+            self.write_all(&b[..len])?;
+        }
+        Ok(size)
+    }
+
+    /// Forward data in both directions until both sides close connection
+    /// or there is no data longer than `idle_timeout`,
+    /// returns sizes of data sent from self and from `http`
+    pub fn duplex(&mut self, http: &mut Self, idle_timeout: Duration) -> Result<(usize, usize)> {
+        self.socket.set_read_timeout(Some(TUNNEL_POLL_INTERVAL))?;
+        http.socket.set_read_timeout(Some(TUNNEL_POLL_INTERVAL))?;
+        let mut sizes = (0, 0);
+        let (mut open, mut http_open) = (true, true);
+        let mut b = [0; CHUNK_SIZE];
+        let mut active = Instant::now();
+        while (open || http_open) && active.elapsed() < idle_timeout {
+            if open {
+                match Http::pump(self, http, &mut b)? {
+                    Some(0) => open = false,
+                    Some(len) => {
+                        sizes.0 += len;
+                        active = Instant::now();
+                    }
+                    None => (),
                 }
-                false
-            });
+            }
+            if http_open {
+                match Http::pump(http, self, &mut b)? {
+                    Some(0) => http_open = false,
+                    Some(len) => {
+                        sizes.1 += len;
+                        active = Instant::now();
+                    }
+                    None => (),
+                }
+            }
+        }
+        self.socket.set_read_timeout(None)?;
+        http.socket.set_read_timeout(None)?;
+        Ok(sizes)
+    }
 
-            size += buf.len();
+    /// Move available data from one side of tunnel to other one, returns [`None`] if there is no data yet
+    /// and `Some(0)` if `from` is closed, closing is passed to `to` by shutdown of writing
+    fn pump(from: &mut Self, to: &mut Self, b: &mut [u8]) -> Result<Option<usize>> {
+        let len = match from.read(b) {
+            Ok(len) => len,
+            Err(e) if is_timeout(&e) => return Ok(None),
+            // TLS peer could close connection without close notify
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => 0,
+            Err(e) => return Err(e),
+        };
+        if len == 0 {
+            to.close_notify()?;
+            // Peer could close connection already
+            to.socket.shutdown(Shutdown::Write).ok();
+            return Ok(Some(0));
+        }
+        to.write_all(&b[..len])?;
+        to.flush()?;
+        Ok(Some(len))
+    }
 
-            if buf.len() == 0 {
-                break;
+    /// Forward exactly `length` bytes of body from `from` to self
+    pub fn forward_length(&mut self, from: &mut Self, length: usize) -> Result<usize> {
+        let mut size: usize = 0;
+        let mut chunk = [0; CHUNK_SIZE];
+        while size < length {
+            let max = CHUNK_SIZE.min(length - size);
+            let len = from.read(&mut chunk[..max])?;
+            if len == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Connection closed before end of body",
+                ));
             }
+            self.write_all(&chunk[..len])?;
+            size += len;
+        }
+        Ok(size)
+    }
 
-            self.write(&buf)?;
+    /// Forward body like [`Http::forward_body`], but stop with [`LimitError`] of `413`
+    /// when body is longer than `max_size`
+    pub fn forward_body_limited(
+        &mut self,
+        from: &mut Self,
+        body: &Body,
+        max_size: usize,
+    ) -> Result<usize> {
+        match body {
+            Body::Length(len) if *len > max_size => {
+                Err(LimitError::reject(413, "Request body is too large"))
+            }
+            Body::Chunked => self.forward_chunks(from, max_size),
+            _ => self.forward_body(from, body),
         }
+    }
 
+    /// Forward chunked body from `from` to self as is, up to the last chunk and trailers
+    pub fn forward_chunked(&mut self, from: &mut Self) -> Result<usize> {
+        self.forward_chunks(from, usize::MAX)
+    }
+
+    fn forward_chunks(&mut self, from: &mut Self, max_size: usize) -> Result<usize> {
+        let mut size: usize = 0;
+        loop {
+            let line = from.read_line()?;
+            let chunk_size = Http::parse_chunk_size(&line)?;
+            if chunk_size > max_size - size {
+                return Err(LimitError::reject(413, "Request body is too large"));
+            }
+            self.write_all(&line)?;
+            if chunk_size == 0 {
+                break;
+            }
+            // Only chunk data is counted by limit
+            size += self.forward_length(from, chunk_size)?;
+            from.read_chunk_end()?;
+            self.write_all(CRLF.as_bytes())?;
+        }
+        loop {
+            let line = from.read_line()?;
+            self.write_all(&line)?;
+            if line == CRLF.as_bytes() || line == b"\n" {
+                break;
+            }
+        }
         Ok(size)
     }
 
+    /// Parse size of chunk from chunk header line like `1a;ext=val\r\n`,
+    /// size has only hex digits, so it can't be read differently by target
+    fn parse_chunk_size(line: &[u8]) -> Result<usize> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Invalid chunk size line {:?}",
+                    String::from_utf8_lossy(line)
+                ),
+            )
+        };
+        let line = line.strip_suffix(CRLF.as_bytes()).ok_or_else(invalid)?;
+        let digits = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+        let (size, ext) = line.split_at(digits);
+        if size.is_empty() || !(ext.is_empty() || ext.starts_with(b";")) {
+            return Err(invalid());
+        }
+        let size = str::from_utf8(size).map_err(|_| invalid())?;
+        usize::from_str_radix(size, 16).map_err(|_| invalid())
+    }
+
+    /// Read CRLF following chunk data
+    fn read_chunk_end(&mut self) -> Result<()> {
+        let mut end = [0; 2];
+        self.read_exact(&mut end)?;
+        if end != CRLF.as_bytes() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Chunk data is not followed by CRLF",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read one line including line ending
+    pub fn read_line(&mut self) -> Result<Vec<u8>> {
+        let mut buf: Vec<u8> = vec![];
+        loop {
+            let mut b = [0; 1];
+            let len = self.read(&mut b)?;
+            if len == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Connection closed before end of line",
+                ));
+            }
+            buf.push(b[0]);
+            if b[0] == b'\n' {
+                break;
+            }
+        }
+        Ok(buf)
+    }
+
     /// Read request headers by one byte for fist empty line
     pub fn read_headers(&mut self) -> Result<Vec<u8>> {
+        self.read_head(None, usize::MAX)
+    }
+
+    /// Read request headers waiting no longer than `idle` for first byte
+    /// and no longer than `timeout` for the rest of them.
+    /// Headers are empty if there is no data during `idle`, [`ErrorKind::TimedOut`] is returned
+    /// if headers are not completed in `timeout`, [`LimitError`] with `431`
+    /// if they are longer than `max_size`
+    pub fn read_headers_timeout(
+        &mut self,
+        idle: Duration,
+        timeout: Duration,
+        max_size: usize,
+    ) -> Result<Vec<u8>> {
+        self.socket.set_read_timeout(Some(idle))?;
+        self.read_head(Some(timeout), max_size)
+    }
+
+    fn read_head(&mut self, timeout: Option<Duration>, max_size: usize) -> Result<Vec<u8>> {
         let mut buf: Vec<u8> = vec![];
+        let mut deadline = None;
         loop {
+            if buf.len() >= max_size {
+                return Err(LimitError::reject(431, "Request headers are too large"));
+            }
             let mut b = [0; 1];
-            let len = self.read(&mut b)?;
+            let len = match self.read(&mut b) {
+                Err(e) if is_timeout(&e) && timeout.is_some() => {
+                    if buf.is_empty() {
+                        return Ok(buf);
+                    }
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        "Request headers are not received in time",
+                    ));
+                }
+                res => res?,
+            };
             if len == 0 {
                 break;
             }
+            // Every next byte is waited only for the rest of time since first one
+            if let Some(timeout) = timeout {
+                let deadline = *deadline.get_or_insert_with(|| Instant::now() + timeout);
+                let rest = deadline.saturating_duration_since(Instant::now());
+                self.socket
+                    .set_read_timeout(Some(rest.max(Duration::from_millis(1))))?;
+            }
             let b = b[0];
             let len = buf.len();
             if len > 2
@@ -129,19 +618,55 @@ impl Http {
     }
 }
 
+/// Error is caused by read or write timeout of socket
+pub fn is_timeout(err: &Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
 impl Read for Http {
     /// Read chunk bytes from request
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(conn) = self.tls.as_mut() {
+            return tls_io(conn, &mut self.socket, |stream| stream.read(buf));
+        }
         self.socket.read(buf)
     }
 }
 
 impl Write for Http {
     fn write(&mut self, data: &[u8]) -> Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(conn) = self.tls.as_mut() {
+            return tls_io(conn, &mut self.socket, |stream| stream.write(data));
+        }
         self.socket.write(data)
     }
 
     fn flush(&mut self) -> Result<()> {
+        #[cfg(feature = "tls")]
+        if let Some(conn) = self.tls.as_mut() {
+            return tls_io(conn, &mut self.socket, |stream| stream.flush());
+        }
         self.socket.flush()
     }
 }
+
+#[cfg(feature = "tls")]
+trait ReadWrite: Read + Write {}
+
+#[cfg(feature = "tls")]
+impl<T: Read + Write> ReadWrite for T {}
+
+/// Run IO operation with plain data of TLS session
+#[cfg(feature = "tls")]
+fn tls_io<T>(
+    conn: &mut rustls::Connection,
+    socket: &mut TcpStream,
+    f: impl FnOnce(&mut dyn ReadWrite) -> Result<T>,
+) -> Result<T> {
+    match conn {
+        rustls::Connection::Client(conn) => f(&mut rustls::Stream::new(conn, socket)),
+        rustls::Connection::Server(conn) => f(&mut rustls::Stream::new(conn, socket)),
+    }
+}
@@ -0,0 +1,362 @@
+//! Module [`Head`].
+//! Parsing of HTTP/1.x request and response heads over bytes, results borrow from buffer
+use std::{
+    borrow::Cow,
+    io::{Error, ErrorKind, Result},
+    str,
+};
+
+/// Request line like `GET /path?query HTTP/1.1`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestLine<'a> {
+    pub method: &'a str,
+    pub target: &'a str,
+    pub version: &'a str,
+}
+
+/// Status line like `HTTP/1.1 200 OK`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatusLine<'a> {
+    pub version: &'a str,
+    pub code: u16,
+    pub reason: &'a str,
+}
+
+/// First line of head
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StartLine<'a> {
+    Request(RequestLine<'a>),
+    Status(StatusLine<'a>),
+    /// Line is neither request line nor status line
+    Invalid(&'a [u8]),
+}
+
+/// Header field, name and value are slices of its line without surrounding whitespaces
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+    /// Whole line without line ending as it was received
+    pub line: &'a [u8],
+}
+
+/// Header fields in order of head, lookup by name ignores case
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fields<'a> {
+    list: Vec<Field<'a>>,
+    /// Count of lines which are not header fields
+    invalid: usize,
+}
+
+/// Head of request or response
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Head<'a> {
+    pub start: StartLine<'a>,
+    pub fields: Fields<'a>,
+    /// Size of head in buffer including empty line
+    pub len: usize,
+    /// Head is ended by empty line
+    pub complete: bool,
+}
+
+impl<'a> RequestLine<'a> {
+    /// Parse line like `GET / HTTP/1.1` without line ending
+    pub fn parse(line: &'a [u8]) -> Option<Self> {
+        let mut parts = line.splitn(3, |b| *b == b' ');
+        let method = parts.next().filter(|m| is_token(m))?;
+        let target = parts.next().filter(|t| !t.is_empty())?;
+        let version = parts.next().filter(|v| is_version(v))?;
+        if target
+            .iter()
+            .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+        {
+            return None;
+        }
+        Some(Self {
+            method: str::from_utf8(method).ok()?,
+            target: str::from_utf8(target).ok()?,
+            version: str::from_utf8(version).ok()?,
+        })
+    }
+
+    /// Path of target, like `/path` of `/path?query` or `http://host/path?query`,
+    /// it is `/` for authority and asterisk forms
+    pub fn path(&self) -> &'a str {
+        let target = match self.target.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("/", |pos| &rest[pos..]),
+            None if self.target.starts_with('/') => self.target,
+            None => "/",
+        };
+        target.split('?').next().unwrap_or(target)
+    }
+
+    /// Query of target with leading `?`, it is empty if target has no query
+    pub fn query(&self) -> &'a str {
+        match self.target.find('?') {
+            Some(pos) => &self.target[pos..],
+            None => "",
+        }
+    }
+}
+
+impl<'a> StatusLine<'a> {
+    /// Parse line like `HTTP/1.1 200 OK` without line ending, reason may be missing
+    pub fn parse(line: &'a [u8]) -> Option<Self> {
+        let mut parts = line.splitn(3, |b| *b == b' ');
+        let version = parts.next().filter(|v| is_version(v))?;
+        let code = parts
+            .next()
+            .filter(|c| c.len() == 3 && c.iter().all(u8::is_ascii_digit))?;
+        let reason = parts.next().unwrap_or(b"");
+        Some(Self {
+            version: str::from_utf8(version).ok()?,
+            code: code
+                .iter()
+                .fold(0, |code, b| code * 10 + u16::from(b - b'0')),
+            reason: str::from_utf8(reason).ok()?.trim(),
+        })
+    }
+}
+
+impl<'a> StartLine<'a> {
+    /// Parse request line or status line by its first word
+    pub fn parse(line: &'a [u8]) -> Self {
+        let parsed = match line.starts_with(b"HTTP/") {
+            true => StatusLine::parse(line).map(StartLine::Status),
+            false => RequestLine::parse(line).map(StartLine::Request),
+        };
+        parsed.unwrap_or(StartLine::Invalid(line))
+    }
+
+    /// Protocol version like `HTTP/1.1`
+    pub fn version(&self) -> Option<&'a str> {
+        match self {
+            StartLine::Request(line) => Some(line.version),
+            StartLine::Status(line) => Some(line.version),
+            StartLine::Invalid(_) => None,
+        }
+    }
+}
+
+impl<'a> Field<'a> {
+    /// Parse line like `Name: value` without line ending, there is no whitespace before colon
+    pub fn parse(line: &'a [u8]) -> Option<Self> {
+        let colon = line.iter().position(|b| *b == b':')?;
+        let name = &line[..colon];
+        if !is_token(name) {
+            return None;
+        }
+        Some(Self {
+            name: str::from_utf8(name).ok()?,
+            value: trim(&line[colon + 1..]),
+            line,
+        })
+    }
+
+    /// Check name ignoring case
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    /// Value as string, invalid UTF-8 is replaced
+    pub fn value_str(&self) -> Cow<'a, str> {
+        String::from_utf8_lossy(self.value)
+    }
+}
+
+impl<'a> Fields<'a> {
+    /// Parse header fields from lines until empty one, returns fields and size of parsed lines
+    pub fn parse(buf: &'a [u8]) -> (Self, usize, bool) {
+        let mut fields = Fields::default();
+        let mut lines = Lines { buf, pos: 0 };
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                return (fields, lines.pos, true);
+            }
+            match Field::parse(line) {
+                Some(field) => fields.list.push(field),
+                None => fields.invalid += 1,
+            }
+        }
+        (fields, lines.pos, false)
+    }
+
+    /// Get value of first field with name
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.get_all(name).next()
+    }
+
+    /// Get values of all fields with name in order
+    pub fn get_all<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'a [u8]> + 'b {
+        self.list.iter().filter(|f| f.is(name)).map(|f| f.value)
+    }
+
+    /// Get comma separated elements of all fields with name, empty ones are skipped
+    pub fn tokens<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'a [u8]> + 'b {
+        self.get_all(name)
+            .flat_map(|v| v.split(|b| *b == b','))
+            .map(trim)
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Check element of comma separated fields ignoring case
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.tokens(name)
+            .any(|v| v.eq_ignore_ascii_case(token.as_bytes()))
+    }
+
+    /// Value of first `Content-Length` field if it has digits only
+    pub fn content_length(&self) -> Option<usize> {
+        let value = self.get("content-length")?;
+        if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        str::from_utf8(value).ok()?.parse().ok()
+    }
+
+    /// Body is chunked when `chunked` is the last coding of `Transfer-Encoding`
+    pub fn is_chunked(&self) -> bool {
+        self.tokens("transfer-encoding")
+            .last()
+            .is_some_and(|v| v.eq_ignore_ascii_case(b"chunked"))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Field<'a>> {
+        self.list.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
+
+impl<'a> Head<'a> {
+    /// Parse start line and header fields, lines which are not header fields are skipped,
+    /// head without start line like trailers has only fields
+    pub fn parse(buf: &'a [u8]) -> Self {
+        let mut lines = Lines { buf, pos: 0 };
+        let line = lines.next().unwrap_or(b"");
+        let start = StartLine::parse(line);
+        if matches!(start, StartLine::Invalid(_)) && Field::parse(line).is_some() {
+            lines.pos = 0;
+        }
+        let (fields, len, complete) = Fields::parse(&buf[lines.pos..]);
+        Self {
+            start,
+            fields,
+            len: lines.pos + len,
+            complete,
+        }
+    }
+
+    /// Parse head ended by empty line with valid start line and header fields,
+    /// bare CR and NUL are not allowed in header lines
+    pub fn parse_strict(buf: &'a [u8]) -> Result<Self> {
+        let head = Head::parse(buf);
+        if let StartLine::Invalid(_) = head.start {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid start line"));
+        }
+        if head.fields.invalid > 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid header line"));
+        }
+        if head
+            .fields
+            .iter()
+            .any(|f| f.line.iter().any(|b| *b == b'\r' || *b == 0))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid character in header line",
+            ));
+        }
+        if !head.complete {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Head is not complete"));
+        }
+        Ok(head)
+    }
+
+    pub fn request_line(&self) -> Option<&RequestLine<'a>> {
+        match &self.start {
+            StartLine::Request(line) => Some(line),
+            _ => None,
+        }
+    }
+
+    pub fn status_line(&self) -> Option<&StatusLine<'a>> {
+        match &self.start {
+            StartLine::Status(line) => Some(line),
+            _ => None,
+        }
+    }
+
+    /// Connection is persistent by default since `HTTP/1.1` unless `Connection: close`
+    pub fn keep_alive(&self) -> bool {
+        if self.fields.has_token("connection", "close") {
+            return false;
+        }
+        match self.start.version() {
+            Some("HTTP/1.0") => self.fields.has_token("connection", "keep-alive"),
+            _ => true,
+        }
+    }
+
+    /// Protocol change is requested by `Connection: upgrade` and not empty `Upgrade`
+    pub fn upgrade(&self) -> bool {
+        self.fields.has_token("connection", "upgrade")
+            && self.fields.get("upgrade").is_some_and(|v| !v.is_empty())
+    }
+}
+
+/// Lines ended by `\n` with optional `\r`, the last line may have no ending
+struct Lines<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.buf[self.pos..];
+        if rest.is_empty() {
+            return None;
+        }
+        let (line, size) = match rest.iter().position(|b| *b == b'\n') {
+            Some(end) => (&rest[..end], end + 1),
+            None => (rest, rest.len()),
+        };
+        self.pos += size;
+        Some(line.strip_suffix(b"\r").unwrap_or(line))
+    }
+}
+
+/// Not empty and has only token characters
+pub(crate) fn is_token(name: &[u8]) -> bool {
+    !name.is_empty()
+        && name
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(b))
+}
+
+/// Version like `HTTP/1.1`
+fn is_version(version: &[u8]) -> bool {
+    matches!(version, [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+        if major.is_ascii_digit() && minor.is_ascii_digit())
+}
+
+/// Strip spaces and tabs around value
+fn trim(value: &[u8]) -> &[u8] {
+    let start = value
+        .iter()
+        .position(|b| *b != b' ' && *b != b'\t')
+        .unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|b| *b != b' ' && *b != b'\t')
+        .map_or(start, |end| end + 1);
+    &value[start..end]
+}
@@ -0,0 +1,213 @@
+//! Module [`Pool`].
+//! Shared idle connections to targets
+use super::{connector::Connector, Http};
+use crate::prelude::constants::{
+    POOL_IDLE_TIMEOUT, POOL_MAX_IDLE, POOL_MAX_PER_HOST, POOL_WAIT_TIMEOUT,
+};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Read, Result},
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Limits of connection pool
+#[derive(Clone, Copy, Debug)]
+pub struct PoolConfig {
+    /// Max count of idle connections kept for one target
+    pub max_idle: usize,
+    /// Max count of idle and borrowed connections to one target
+    pub max_per_host: usize,
+    /// Time after which idle connection is closed
+    pub idle_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig::new()
+    }
+}
+
+impl PoolConfig {
+    pub fn new() -> Self {
+        Self {
+            max_idle: POOL_MAX_IDLE,
+            max_per_host: POOL_MAX_PER_HOST,
+            idle_timeout: POOL_IDLE_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Host {
+    idle: Vec<(Http, Instant)>,
+    active: usize,
+}
+
+/// Thread safe pool of idle target connections keyed by target address
+pub struct Pool {
+    config: PoolConfig,
+    connector: Arc<Connector>,
+    hosts: Mutex<HashMap<String, Host>>,
+    released: Condvar,
+}
+
+/// Connection borrowed from [`Pool`].
+/// Dropped connection is closed, call [`Pooled::release`] to return it to pool
+pub struct Pooled {
+    http: Option<Http>,
+    address: String,
+    pool: Arc<Pool>,
+    /// Connection was taken from idle ones
+    pub reused: bool,
+}
+
+impl Pool {
+    pub fn new(config: PoolConfig) -> Self {
+        Self {
+            config,
+            connector: Arc::new(Connector::default()),
+            hosts: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Set creator of new connections
+    pub fn with_connector(mut self, connector: Arc<Connector>) -> Self {
+        self.connector = connector;
+        self
+    }
+
+    /// Borrow idle connection to target or create new one
+    pub fn get(self: &Arc<Self>, address: &str) -> Result<Pooled> {
+        self.checkout(address, true)
+    }
+
+    /// Create new connection to target bypassing idle ones
+    pub fn connect(self: &Arc<Self>, address: &str) -> Result<Pooled> {
+        self.checkout(address, false)
+    }
+
+    /// Count of idle connections to target
+    pub fn idle(&self, address: &str) -> usize {
+        let hosts = self.hosts.lock().unwrap();
+        hosts.get(address).map(|h| h.idle.len()).unwrap_or(0)
+    }
+
+    fn checkout(self: &Arc<Self>, address: &str, reuse: bool) -> Result<Pooled> {
+        let deadline = Instant::now() + POOL_WAIT_TIMEOUT;
+        let mut hosts = self.hosts.lock().unwrap();
+        loop {
+            let host = hosts.entry(address.to_string()).or_default();
+            let idle_timeout = self.config.idle_timeout;
+            host.idle
+                .retain(|(_, since)| since.elapsed() < idle_timeout);
+
+            if reuse {
+                while let Some((http, _)) = host.idle.pop() {
+                    if Pool::is_alive(&http) {
+                        host.active += 1;
+                        return Ok(self.pooled(address, http, true));
+                    }
+                }
+            } else if host.active + host.idle.len() >= self.config.max_per_host {
+                // Free slot for new connection by closing the oldest idle one
+                if !host.idle.is_empty() {
+                    host.idle.remove(0);
+                }
+            }
+
+            if host.active + host.idle.len() < self.config.max_per_host {
+                host.active += 1;
+                break;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::new(
+                    ErrorKind::WouldBlock,
+                    format!("Connections limit reached for target {}", address),
+                ));
+            }
+            hosts = self.released.wait_timeout(hosts, deadline - now).unwrap().0;
+        }
+        drop(hosts);
+
+        match self.connector.connect(address, None) {
+            Ok(http) => Ok(self.pooled(address, http, false)),
+            Err(err) => {
+                self.free(address, None);
+                Err(err)
+            }
+        }
+    }
+
+    fn pooled(self: &Arc<Self>, address: &str, http: Http, reused: bool) -> Pooled {
+        Pooled {
+            http: Some(http),
+            address: address.to_string(),
+            pool: Arc::clone(self),
+            reused,
+        }
+    }
+
+    /// Return borrowed slot of target and keep connection if there is room for it
+    fn free(&self, address: &str, http: Option<Http>) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(host) = hosts.get_mut(address) {
+            host.active -= 1;
+            if let Some(http) = http {
+                if host.idle.len() < self.config.max_idle {
+                    host.idle.push((http, Instant::now()));
+                }
+            }
+        }
+        self.released.notify_one();
+    }
+
+    /// Check that idle connection is not closed by target and has no unexpected data
+    fn is_alive(http: &Http) -> bool {
+        let mut socket = &http.socket;
+        if socket.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut b = [0; 1];
+        let alive = match socket.read(&mut b) {
+            Err(e) => e.kind() == ErrorKind::WouldBlock,
+            Ok(_) => false,
+        };
+        alive && socket.set_nonblocking(false).is_ok()
+    }
+}
+
+impl Pooled {
+    /// Return connection to pool for reuse
+    pub fn release(mut self) {
+        let http = self.http.take();
+        self.pool.free(&self.address, http);
+    }
+}
+
+impl Deref for Pooled {
+    type Target = Http;
+
+    fn deref(&self) -> &Http {
+        self.http.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Pooled {
+    fn deref_mut(&mut self) -> &mut Http {
+        self.http.as_mut().unwrap()
+    }
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        if let Some(http) = self.http.take() {
+            drop(http);
+            self.pool.free(&self.address, None);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Module [`ProxyHeader`].
+//! HAProxy PROXY protocol v1 and v2 headers carrying addresses of client connection
+use super::CRLF;
+use std::{
+    io::{Error, ErrorKind, Read, Result},
+    net::{IpAddr, SocketAddr},
+};
+
+/// Signature of binary header of v2
+const SIGNATURE_V2: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Max length of text header of v1
+const MAX_LENGTH_V1: usize = 107;
+
+/// Version of PROXY protocol
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyVersion {
+    /// Text header like `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443`
+    V1,
+    /// Binary header
+    V2,
+}
+
+/// Addresses of client connection passed by PROXY protocol
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// Address of client
+    pub source: SocketAddr,
+    /// Address which client is connected to
+    pub destination: SocketAddr,
+}
+
+impl ProxyHeader {
+    /// Read header of any version, returns [`None`] if header doesn't carry addresses
+    /// like `PROXY UNKNOWN` or v2 `LOCAL` command
+    pub fn read(reader: &mut impl Read) -> Result<Option<Self>> {
+        let mut start = [0; 5];
+        reader.read_exact(&mut start)?;
+        if &start == b"PROXY" {
+            return ProxyHeader::read_v1(reader);
+        }
+        if start == SIGNATURE_V2[..5] {
+            return ProxyHeader::read_v2(reader);
+        }
+        Err(invalid("PROXY protocol header is missing"))
+    }
+
+    /// Encode header of version, addresses of different families are passed as IPv6
+    pub fn encode(&self, version: ProxyVersion) -> Vec<u8> {
+        let (source, destination) = match (self.source.ip(), self.destination.ip()) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => (IpAddr::V4(s), IpAddr::V4(d)),
+            (s, d) => (IpAddr::V6(to_ipv6(s)), IpAddr::V6(to_ipv6(d))),
+        };
+        let (source_port, destination_port) = (self.source.port(), self.destination.port());
+        match version {
+            ProxyVersion::V1 => {
+                let family = match source {
+                    IpAddr::V4(_) => "TCP4",
+                    IpAddr::V6(_) => "TCP6",
+                };
+                format!(
+                    "PROXY {family} {source} {destination} {source_port} {destination_port}{CRLF}"
+                )
+                .into_bytes()
+            }
+            ProxyVersion::V2 => {
+                let mut res = SIGNATURE_V2.to_vec();
+                // Version 2 with command PROXY
+                res.push(0x21);
+                let mut addresses = vec![];
+                match (source, destination) {
+                    (IpAddr::V4(s), IpAddr::V4(d)) => {
+                        // TCP over IPv4
+                        res.push(0x11);
+                        addresses.extend_from_slice(&s.octets());
+                        addresses.extend_from_slice(&d.octets());
+                    }
+                    (s, d) => {
+                        // TCP over IPv6
+                        res.push(0x21);
+                        addresses.extend_from_slice(&to_ipv6(s).octets());
+                        addresses.extend_from_slice(&to_ipv6(d).octets());
+                    }
+                }
+                addresses.extend_from_slice(&source_port.to_be_bytes());
+                addresses.extend_from_slice(&destination_port.to_be_bytes());
+                res.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+                res.extend_from_slice(&addresses);
+                res
+            }
+        }
+    }
+
+    fn read_v1(reader: &mut impl Read) -> Result<Option<Self>> {
+        let mut line = b"PROXY".to_vec();
+        while !line.ends_with(CRLF.as_bytes()) {
+            if line.len() >= MAX_LENGTH_V1 {
+                return Err(invalid("PROXY protocol v1 header is too long"));
+            }
+            let mut b = [0; 1];
+            reader.read_exact(&mut b)?;
+            line.push(b[0]);
+        }
+        let line = String::from_utf8(line).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let parts: Vec<&str> = line.trim_end().split(' ').collect();
+        match parts.as_slice() {
+            ["PROXY", "UNKNOWN", ..] => Ok(None),
+            ["PROXY", "TCP4" | "TCP6", source, destination, source_port, destination_port] => {
+                let address = |ip: &str, port: &str| -> Result<SocketAddr> {
+                    let ip: IpAddr = ip.parse().map_err(|_| invalid("Invalid address"))?;
+                    let port: u16 = port.parse().map_err(|_| invalid("Invalid port"))?;
+                    Ok(SocketAddr::new(ip, port))
+                };
+                Ok(Some(Self {
+                    source: address(source, source_port)?,
+                    destination: address(destination, destination_port)?,
+                }))
+            }
+            _ => Err(invalid("Invalid PROXY protocol v1 header")),
+        }
+    }
+
+    fn read_v2(reader: &mut impl Read) -> Result<Option<Self>> {
+        let mut head = [0; 11];
+        reader.read_exact(&mut head)?;
+        if head[..7] != SIGNATURE_V2[5..] || head[7] >> 4 != 2 {
+            return Err(invalid("Invalid PROXY protocol v2 header"));
+        }
+        let (command, family) = (head[7] & 0x0f, head[8] >> 4);
+        let mut data = vec![0; u16::from_be_bytes([head[9], head[10]]) as usize];
+        reader.read_exact(&mut data)?;
+
+        // Command LOCAL is sent by health checks of balancer
+        if command == 0 {
+            return Ok(None);
+        }
+        let port = |pos: usize| u16::from_be_bytes([data[pos], data[pos + 1]]);
+        match family {
+            1 if data.len() >= 12 => {
+                let source: [u8; 4] = data[..4].try_into().unwrap();
+                let destination: [u8; 4] = data[4..8].try_into().unwrap();
+                Ok(Some(Self {
+                    source: SocketAddr::new(IpAddr::from(source), port(8)),
+                    destination: SocketAddr::new(IpAddr::from(destination), port(10)),
+                }))
+            }
+            2 if data.len() >= 36 => {
+                let source: [u8; 16] = data[..16].try_into().unwrap();
+                let destination: [u8; 16] = data[16..32].try_into().unwrap();
+                Ok(Some(Self {
+                    source: SocketAddr::new(IpAddr::from(source), port(32)),
+                    destination: SocketAddr::new(IpAddr::from(destination), port(34)),
+                }))
+            }
+            // Unix sockets and unspecified family don't carry IP addresses
+            0 | 3 => Ok(None),
+            _ => Err(invalid("Invalid addresses of PROXY protocol v2 header")),
+        }
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
@@ -1,5 +1,7 @@
-///! Module [`Request`]
-use crate::http::headers::Headers;
+//! Module [`Request`]
+use crate::http::{
+    headers::Headers, limits::LimitError, method::Method, uri::Uri, version::Version,
+};
 #[cfg(feature = "napi")]
 use napi_derive::napi;
 use regex::Regex;
@@ -11,18 +13,21 @@ use std::{io::Result, str};
 #[derive(Debug, Serialize, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Request {
-    pub url: String,
+    /// Request target, it is `/` for response
+    pub uri: Uri,
     pub host: String,
     pub peer_addr: String,
-    pub protocol: String,
-    pub method: String,
-    pub content_length: u32,
+    pub version: Version,
+    /// Method of request, it is `GET` for response
+    pub method: Method,
+    /// Value of `Content-Length`, it is `i64` to be a number in JS
+    pub content_length: i64,
     pub ttl: u32,
     pub headers: Headers,
     pub body: String,
-    pub query: String,
     pub error: String,
     pub chunked: bool,
+    pub keep_alive: bool,
 }
 
 pub struct Socket {
@@ -34,10 +39,12 @@ pub struct Socket {
 
 impl Request {
     pub fn new(socket: Socket, buffer: Vec<u8>) -> Result<Self> {
-        let headers = Headers::from_bytes(&buffer)?;
-        Ok(Request::create(socket, headers))
+        let headers = Headers::from(buffer);
+        Request::create(socket, headers)
     }
 
+    /// Create request from parsed headers, [`LimitError`] with `400` is returned
+    /// if target of request line is invalid
     pub fn create(
         Socket {
             host,
@@ -46,26 +53,30 @@ impl Request {
             error,
         }: Socket,
         headers: Headers,
-    ) -> Self {
-        let mut content_length: u32 = 0;
-        let content_length_op = Headers::get_content_length(&headers.raw);
-        if let Some(val) = content_length_op {
-            content_length = val;
-        }
-        Request {
+    ) -> Result<Self> {
+        let uri = match headers.target() {
+            Some(target) => {
+                Uri::parse(target).map_err(|_| LimitError::reject(400, "Invalid request target"))?
+            }
+            None => Uri::default(),
+        };
+        Ok(Request {
             host,
             peer_addr,
-            url: Headers::get_url(&headers.raw),
-            protocol: Headers::get_protocol(&headers.raw),
-            method: Headers::get_method(&headers.raw),
-            content_length,
+            uri,
+            version: headers.version().unwrap_or_default(),
+            method: headers.method().cloned().unwrap_or_default(),
+            content_length: headers
+                .content_length()
+                .and_then(|len| i64::try_from(len).ok())
+                .unwrap_or(0),
             ttl,
             body: "".to_string(),
-            query: Headers::get_query(&headers.raw),
             error,
-            chunked: Headers::get_chunked(&headers.raw),
+            chunked: headers.is_chunked(),
+            keep_alive: headers.keep_alive(),
             headers,
-        }
+        })
     }
 
     pub fn change_host(&mut self, target: &str) -> Result<()> {
@@ -77,17 +88,26 @@ impl Request {
         Ok(())
     }
 
+    /// Change request target in request line, like `/path?query`
+    pub fn set_target(&mut self, target: &str) -> Result<()> {
+        let uri = Uri::parse(target)?;
+        let prefix = format!("{} {} {}", self.method, target, self.version);
+        self.headers = self.headers.with_prefix(&prefix);
+        self.uri = uri;
+        Ok(())
+    }
+
     pub fn set_body(&mut self, body: String) {
         self.body = body;
     }
 }
 
 #[allow(dead_code)]
-fn get_status(raw: &String) -> u16 {
+fn get_status(raw: &str) -> u16 {
     let reg = Regex::new(r"\d{3}").unwrap();
-    let capts = reg.captures(raw.as_str());
+    let capts = reg.captures(raw);
     let mut status: u16 = 500;
-    if let None = capts {
+    if capts.is_none() {
         return status;
     }
     let capts = capts.unwrap();
@@ -99,11 +119,11 @@ fn get_status(raw: &String) -> u16 {
 }
 
 #[allow(dead_code)]
-fn get_status_text(raw: &String) -> String {
+fn get_status_text(raw: &str) -> String {
     let reg = Regex::new(r"\d{3}[ \w\-]+").unwrap();
-    let capts = reg.captures(raw.as_str());
+    let capts = reg.captures(raw);
     let mut status_text: String = "Internal Server Error".to_string();
-    if let None = capts {
+    if capts.is_none() {
         return status_text;
     }
     let capts = capts.unwrap();
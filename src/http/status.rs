@@ -1,12 +1,10 @@
-///! Module [`StatusDefault`]
-use std::str;
-
-use regex::Regex;
+//! Module [`StatusDefault`]
+use std::{fmt, str};
 
 use crate::{http::CRLF, prelude::constants::HTTP_VERSION_DEFAULT};
 
 /// HTTP status
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Status {
     pub text: String,
     pub code: u16,
@@ -289,13 +287,12 @@ impl Status {
     /// Get HTTP protocol prefix like `HTTP/1.1 200 OK`
     pub fn to_full_string(&self) -> String {
         let d = self.to_string();
-        let res = Regex::new(format!(r"{CRLF}$").as_str())
-            .unwrap()
-            .replace_all(d.as_str(), "");
-        format!("{HTTP_VERSION_DEFAULT} {}", res)
+        format!("{HTTP_VERSION_DEFAULT} {}", d.trim_end_matches(CRLF))
     }
+}
 
-    pub fn to_string(&self) -> String {
-        format!("{} {}", self.code, self.text)
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.text)
     }
 }
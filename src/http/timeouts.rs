@@ -0,0 +1,74 @@
+//! Module [`Timeouts`].
+//! Limits of waiting for clients and targets
+use crate::prelude::constants::{
+    BODY_TIMEOUT, CONNECT_TIMEOUT, HEADER_TIMEOUT, KEEP_ALIVE_TIMEOUT, RESPONSE_TIMEOUT,
+    TUNNEL_IDLE_TIMEOUT,
+};
+use std::time::Duration;
+
+/// Timeouts of proxy server, exceeded ones are answered with `408` or `504`
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    /// Max time of connecting to target, `504` is sent then
+    pub connect: Duration,
+    /// Max time of reading request headers since their first byte, `408` is sent then
+    pub header: Duration,
+    /// Max time without progress of reading request body or writing to client,
+    /// `408` is sent if request body is not received
+    pub body: Duration,
+    /// Max time of waiting for data from target, `504` is sent if response headers are not received
+    pub response: Duration,
+    /// Max time of waiting for next request on keep-alive connection, it is closed then
+    pub idle: Duration,
+    /// Max time without data in both directions of `CONNECT` tunnel or upgraded connection
+    pub tunnel: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts::new()
+    }
+}
+
+impl Timeouts {
+    pub fn new() -> Self {
+        Self {
+            connect: CONNECT_TIMEOUT,
+            header: HEADER_TIMEOUT,
+            body: BODY_TIMEOUT,
+            response: RESPONSE_TIMEOUT,
+            idle: KEEP_ALIVE_TIMEOUT,
+            tunnel: TUNNEL_IDLE_TIMEOUT,
+        }
+    }
+
+    pub fn with_connect(mut self, timeout: Duration) -> Self {
+        self.connect = timeout;
+        self
+    }
+
+    pub fn with_header(mut self, timeout: Duration) -> Self {
+        self.header = timeout;
+        self
+    }
+
+    pub fn with_body(mut self, timeout: Duration) -> Self {
+        self.body = timeout;
+        self
+    }
+
+    pub fn with_response(mut self, timeout: Duration) -> Self {
+        self.response = timeout;
+        self
+    }
+
+    pub fn with_idle(mut self, timeout: Duration) -> Self {
+        self.idle = timeout;
+        self
+    }
+
+    pub fn with_tunnel(mut self, timeout: Duration) -> Self {
+        self.tunnel = timeout;
+        self
+    }
+}
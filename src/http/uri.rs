@@ -0,0 +1,243 @@
+//! Module [`Uri`].
+//! Request target in origin, absolute, authority or asterisk form
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    str::FromStr,
+};
+
+/// Form of request target by RFC 9112
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UriForm {
+    /// `/path?query` of requests to origin server
+    #[default]
+    Origin,
+    /// `http://host:port/path?query` of requests to forward proxy
+    Absolute,
+    /// `host:port` of `CONNECT` requests
+    Authority,
+    /// `*` of server-wide `OPTIONS` requests
+    Asterisk,
+}
+
+/// Request target, path and query are kept percent-encoded as received
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Uri {
+    pub form: UriForm,
+    /// Scheme in lower case of absolute form
+    pub scheme: Option<String>,
+    /// `host:port` of absolute and authority forms
+    pub authority: Option<String>,
+    /// Path starting with `/`, it is `/` for authority form and `*` for asterisk form
+    pub path: String,
+    /// Query without leading `?`
+    pub query: Option<String>,
+}
+
+impl Default for Uri {
+    fn default() -> Self {
+        Self {
+            form: UriForm::Origin,
+            scheme: None,
+            authority: None,
+            path: "/".to_string(),
+            query: None,
+        }
+    }
+}
+
+impl Uri {
+    /// Parse request target of any form
+    pub fn parse(target: &str) -> Result<Self> {
+        if target.is_empty()
+            || target
+                .bytes()
+                .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid request target {:?}", target),
+            ));
+        }
+        if target == "*" {
+            return Ok(Self {
+                form: UriForm::Asterisk,
+                path: "*".to_string(),
+                ..Default::default()
+            });
+        }
+        if target.starts_with('/') {
+            let (path, query) = split_query(target);
+            return Ok(Self {
+                path: path.to_string(),
+                query,
+                ..Default::default()
+            });
+        }
+        if let Some((scheme, rest)) = target.split_once("://") {
+            if !is_scheme(scheme) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid scheme of request target {}", target),
+                ));
+            }
+            let (authority, rest) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+            if authority.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Host of request target {} is empty", target),
+                ));
+            }
+            let (path, query) = split_query(rest);
+            return Ok(Self {
+                form: UriForm::Absolute,
+                scheme: Some(scheme.to_lowercase()),
+                authority: Some(authority.to_string()),
+                path: match path.is_empty() {
+                    true => "/".to_string(),
+                    false => path.to_string(),
+                },
+                query,
+            });
+        }
+        match target.rsplit_once(':') {
+            Some((host, port))
+                if !host.is_empty()
+                    && !port.is_empty()
+                    && port.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                Ok(Self {
+                    form: UriForm::Authority,
+                    authority: Some(target.to_string()),
+                    ..Default::default()
+                })
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown form of request target {}", target),
+            )),
+        }
+    }
+
+    /// Path with decoded percent-encoded bytes, invalid UTF-8 is replaced
+    pub fn decoded_path(&self) -> String {
+        percent_decode(&self.path, false)
+    }
+
+    /// Pairs of decoded keys and values of query in order, `+` is decoded as space
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        let query = match &self.query {
+            Some(query) => query,
+            None => return vec![],
+        };
+        query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key, true), percent_decode(value, true))
+            })
+            .collect()
+    }
+
+    /// Decoded value of first query parameter with key
+    pub fn query_param(&self, key: &str) -> Option<String> {
+        self.query_pairs()
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Target in origin form like `/path?query` for request to origin server
+    pub fn origin(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        }
+    }
+}
+
+impl FromStr for Uri {
+    type Err = Error;
+
+    fn from_str(target: &str) -> Result<Self> {
+        Uri::parse(target)
+    }
+}
+
+impl TryFrom<String> for Uri {
+    type Error = Error;
+
+    fn try_from(target: String) -> Result<Self> {
+        Uri::parse(&target)
+    }
+}
+
+impl From<Uri> for String {
+    fn from(uri: Uri) -> Self {
+        uri.to_string()
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.form {
+            UriForm::Origin => write!(f, "{}", self.origin()),
+            UriForm::Absolute => write!(
+                f,
+                "{}://{}{}",
+                self.scheme.as_deref().unwrap_or("http"),
+                self.authority.as_deref().unwrap_or(""),
+                self.origin()
+            ),
+            UriForm::Authority => write!(f, "{}", self.authority.as_deref().unwrap_or("")),
+            UriForm::Asterisk => write!(f, "*"),
+        }
+    }
+}
+
+#[cfg(feature = "napi")]
+super::napi_string!(Uri);
+
+/// Decode `%XX` sequences, invalid ones are kept as is
+pub fn percent_decode(value: &str, plus_as_space: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                res.push(b);
+                i += 3;
+                continue;
+            }
+            (b'+', _) if plus_as_space => res.push(b' '),
+            (b, _) => res.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&res).to_string()
+}
+
+/// Split target to path and query without `?`
+fn split_query(target: &str) -> (&str, Option<String>) {
+    match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    }
+}
+
+/// Scheme starts with letter and has letters, digits, `+`, `-` and `.`
+fn is_scheme(scheme: &str) -> bool {
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+}
@@ -0,0 +1,67 @@
+//! Module [`Version`].
+//! Version of HTTP/1.x protocol
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    str::FromStr,
+};
+
+/// Version of protocol from request line or status line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Version {
+    Http10,
+    #[default]
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    /// Parse version like `HTTP/1.1`, later minor versions of HTTP/1 are handled as `HTTP/1.1`
+    fn from_str(version: &str) -> Result<Self> {
+        match version.as_bytes() {
+            b"HTTP/1.0" => Ok(Version::Http10),
+            [b'H', b'T', b'T', b'P', b'/', b'1', b'.', minor] if minor.is_ascii_digit() => {
+                Ok(Version::Http11)
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unsupported protocol version {}", version),
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Version {
+    type Error = Error;
+
+    fn try_from(version: String) -> Result<Self> {
+        version.parse()
+    }
+}
+
+impl From<Version> for String {
+    fn from(version: Version) -> Self {
+        version.as_str().to_string()
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "napi")]
+super::napi_string!(Version);
@@ -0,0 +1,81 @@
+//! Module [`KeepAlive`].
+//! Client connections waiting for next request don't hold threads of pool
+use crate::{admin::ConnectionGuard, http::Http};
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Client connection between requests
+pub struct Parked {
+    pub client: Http,
+    pub connection: ConnectionGuard,
+    since: Instant,
+}
+
+/// Idle connections checked by one thread, connections with data of next request
+/// are returned to thread pool
+#[derive(Default)]
+pub struct KeepAlive {
+    state: Mutex<State>,
+    parked: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    list: Vec<Parked>,
+    /// Connection is parked after the last wait
+    fresh: bool,
+}
+
+impl KeepAlive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn park(&self, client: Http, connection: ConnectionGuard) {
+        connection.idle();
+        let mut state = self.state.lock().unwrap();
+        state.list.push(Parked {
+            client,
+            connection,
+            since: Instant::now(),
+        });
+        state.fresh = true;
+        self.parked.notify_one();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().list.is_empty()
+    }
+
+    /// Wait for `interval` or until connection is parked, returns `true` if it is parked
+    pub fn wait(&self, interval: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let (mut state, _) = self
+            .parked
+            .wait_timeout_while(state, interval, |state| !state.fresh)
+            .unwrap();
+        std::mem::take(&mut state.fresh)
+    }
+
+    /// Take connections with data or closed by clients, connections idle longer than `timeout`
+    /// are closed. Returns taken connections and count of closed ones
+    pub fn poll(&self, timeout: Duration) -> (Vec<Parked>, usize) {
+        let mut state = self.state.lock().unwrap();
+        let list = &mut state.list;
+        let mut ready = vec![];
+        let mut closed = 0;
+        for mut parked in std::mem::take(list) {
+            if parked.client.is_readable() {
+                ready.push(parked);
+            } else if parked.since.elapsed() >= timeout {
+                let _ = parked.client.close_notify();
+                closed += 1;
+            } else {
+                list.push(parked);
+            }
+        }
+        (ready, closed)
+    }
+}
@@ -23,6 +23,26 @@
 //!         .expect("Error in proxy");
 //! }
 //! ```
+//! With group of targets and strategy of load balancing
+//! ```no_run
+//! use proxy_server::{
+//!     upstream::{Strategy, Upstream, UpstreamGroup},
+//!     Builder,
+//! };
+//!
+//! fn main() {
+//!     Builder::new()
+//!         .with_targets(
+//!             UpstreamGroup::new(vec![
+//!                 Upstream::new("127.0.0.1:3001").with_weight(2),
+//!                 Upstream::new("127.0.0.1:3002"),
+//!             ])
+//!             .with_strategy(Strategy::WeightedRoundRobin),
+//!         )
+//!         .bind(None)
+//!         .expect("Error in proxy");
+//! }
+//! ```
 //! With check and change target if needed on every request
 //! ```no_run
 //!
@@ -47,22 +67,65 @@
 //! ```
 
 use std::{
-    convert::Infallible,
     io::{Error, ErrorKind, Result, Write},
-    net::{TcpListener, TcpStream},
+    net::{SocketAddr, TcpListener, TcpStream},
     str,
-    thread::sleep,
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
 };
+mod keep_alive;
+use keep_alive::KeepAlive;
 mod thread_pool;
 use thread_pool::ThreadPool;
+pub mod access_log;
+use access_log::{AccessEntry, AccessLog, AccessLogger};
+pub mod admin;
+use admin::{Admin, ConnectionGuard, Connections};
+pub mod cache;
+use cache::{Cache, CacheStore, Cached, Lookup};
+pub mod forward;
+use forward::ForwardProxy;
+pub mod forwarded;
+use forwarded::ForwardedHeaders;
+pub mod health;
+use health::HealthCheck;
 pub mod http;
-use http::Http;
+use http::{
+    body::Body,
+    connector::{Connector, Target},
+    is_timeout,
+    limits::{LimitError, Limits},
+    method::Method,
+    parser::Head,
+    pool::{Pool, PoolConfig},
+    proxy_protocol::{ProxyHeader, ProxyVersion},
+    timeouts::Timeouts,
+    version::Version,
+    Http,
+};
 
 pub mod log;
-use log::{Log, LogLevel, LOG_LEVEL};
+use log::{Log, LogLevel, LogSink, StdoutSink, LOG_LEVEL};
+pub mod metrics;
+use metrics::{Metrics, FORWARD_UPSTREAM};
 pub mod prelude;
+pub mod rewrite;
+use rewrite::{Direction, HeaderRule, Variables};
+pub mod router;
+use router::{Route, Router};
+pub mod shutdown;
+use shutdown::Shutdown;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "tls")]
+use tls::{TlsConfig, UpstreamTls};
+pub mod upstream;
 use prelude::constants::*;
+use upstream::{Balancer, Upstream, UpstreamGroup};
 
 use crate::http::{
     headers::Headers,
@@ -78,12 +141,48 @@ mod tests;
 pub type ChangeTarget = fn(&'static str) -> &'static str;
 
 /// Structure for proxy server configuration
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct Builder {
     pub address: &'static str,
     pub target: &'static str,
+    pub targets: Option<UpstreamGroup>,
+    pub routes: Vec<Route>,
     pub log_level: LogLevel,
+    /// Destination of log messages, stdout by default
+    pub log_sink: Arc<dyn LogSink>,
     pub threads: usize,
+    pub pool: PoolConfig,
+    pub health_check: Option<HealthCheck>,
+    pub forward: Option<ForwardProxy>,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    pub header_rules: Vec<HeaderRule>,
+    pub forwarded_headers: ForwardedHeaders,
+    /// Read PROXY protocol header from every client connection
+    pub proxy_protocol: bool,
+    /// Send PROXY protocol header to targets, connections to targets are not reused then
+    pub upstream_proxy_protocol: Option<ProxyVersion>,
+    pub access_log: Option<AccessLog>,
+    /// Cache of target responses, responses are not stored by default
+    pub cache: Option<Cache>,
+    /// Listen address of admin server with metrics and JSON API
+    pub admin_address: Option<&'static str>,
+    /// Bearer token of admin requests changing proxy
+    pub admin_token: Option<String>,
+    /// Handle stopping [`Builder::bind`]
+    pub shutdown: Shutdown,
+    /// Max time of finishing requests in progress after shutdown is triggered
+    pub shutdown_timeout: Duration,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+    #[cfg(feature = "tls")]
+    pub upstream_tls: Option<UpstreamTls>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
 }
 
 impl Builder {
@@ -92,8 +191,30 @@ impl Builder {
         Self {
             address: PROXY_ADDRESS,
             target: TARGET_ADDRESS,
+            targets: None,
+            routes: vec![],
             log_level: LOG_LEVEL,
+            log_sink: Arc::new(StdoutSink),
             threads: THREADS,
+            pool: PoolConfig::new(),
+            health_check: None,
+            forward: None,
+            timeouts: Timeouts::new(),
+            limits: Limits::new(),
+            header_rules: vec![],
+            forwarded_headers: ForwardedHeaders::new(),
+            proxy_protocol: false,
+            upstream_proxy_protocol: None,
+            access_log: None,
+            cache: None,
+            admin_address: None,
+            admin_token: None,
+            shutdown: Shutdown::new(),
+            shutdown_timeout: SHUTDOWN_TIMEOUT,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "tls")]
+            upstream_tls: None,
         }
     }
 
@@ -109,137 +230,1130 @@ impl Builder {
         self
     }
 
+    /// Set group of targets with strategy of load balancing, it is used instead of single target
+    pub fn with_targets(mut self, targets: UpstreamGroup) -> Self {
+        self.targets = Some(targets);
+        self
+    }
+
+    /// Add route to group of targets, routes are matched in order of adding,
+    /// requests without matched route are sent to default target
+    pub fn with_route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
     /// Set log level of proxy server
     pub fn with_log_level(mut self, log_level: LogLevel) -> Self {
         self.log_level = log_level;
         self
     }
 
+    /// Write log messages to sink instead of stdout
+    pub fn with_log_sink(mut self, log_sink: Arc<dyn LogSink>) -> Self {
+        self.log_sink = log_sink;
+        self
+    }
+
     /// Set proxy server count of used threads
     pub fn with_threads(mut self, threads: usize) -> Self {
         self.threads = threads;
         self
     }
 
-    /// Proxy server listener releasing [`std::net::TcpListener`] via thread pool
-    pub fn bind(mut self, cb: Option<ChangeTarget>) -> Result<Infallible> {
-        let listener = TcpListener::bind(&self.address)?;
+    /// Set limits of target connections pool
+    pub fn with_pool(mut self, pool: PoolConfig) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Set active health checks of targets, unhealthy targets are skipped by load balancing
+    pub fn with_health_check(mut self, health_check: HealthCheck) -> Self {
+        self.health_check = Some(health_check);
+        self
+    }
+
+    /// Serve absolute-form requests and `CONNECT` tunnels as forward proxy,
+    /// origin-form requests are still proxied to targets
+    pub fn with_forward_proxy(mut self, forward: ForwardProxy) -> Self {
+        self.forward = Some(forward);
+        self
+    }
+
+    /// Set limits of request size, requests exceeding them are rejected
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set timeouts of reading from clients and targets
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Add rule changing headers of requests to targets or responses to clients,
+    /// rules are applied in order of adding
+    pub fn with_header_rule(mut self, rule: HeaderRule) -> Self {
+        self.header_rules.push(rule);
+        self
+    }
+
+    /// Set `X-Forwarded-*` and `Forwarded` headers and trusted proxies,
+    /// by default both headers are set and incoming values are replaced
+    pub fn with_forwarded_headers(mut self, forwarded_headers: ForwardedHeaders) -> Self {
+        self.forwarded_headers = forwarded_headers;
+        self
+    }
+
+    /// Require PROXY protocol v1 or v2 header from clients like TCP balancers
+    /// and take address of client from it
+    pub fn with_proxy_protocol(mut self) -> Self {
+        self.proxy_protocol = true;
+        self
+    }
+
+    /// Send PROXY protocol header with address of client to targets,
+    /// every request is sent by new connection then
+    pub fn with_upstream_proxy_protocol(mut self, version: ProxyVersion) -> Self {
+        self.upstream_proxy_protocol = Some(version);
+        self
+    }
+
+    /// Write line per completed exchange to access log
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Store responses of targets by `Cache-Control` and answer repeated requests from cache
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Serve metrics and JSON API of [`Admin`] on separate listener, API changes targets
+    /// and log level without auth unless [`Builder::with_admin_token`] is set,
+    /// so listener should be reachable only from trusted hosts like `127.0.0.1`
+    pub fn with_admin_address(mut self, admin_address: &'static str) -> Self {
+        self.admin_address = Some(admin_address);
+        self
+    }
+
+    /// Require `Authorization: Bearer <token>` for admin requests changing proxy
+    pub fn with_admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(token.to_string());
+        self
+    }
+
+    /// Set handle whose trigger makes [`Builder::bind`] stop accepting and return
+    /// after requests in progress are finished
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Set max time of finishing requests in progress after shutdown is triggered,
+    /// connections are closed then
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Terminate TLS of client connections with certificates from PEM files
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Set CA, client certificate and server name of TLS connections to `https://` targets
+    #[cfg(feature = "tls")]
+    pub fn with_upstream_tls(mut self, upstream_tls: UpstreamTls) -> Self {
+        self.upstream_tls = Some(upstream_tls);
+        self
+    }
+
+    /// Log writing to sink of builder
+    fn log(&self) -> Log {
+        Log::with_sink(&self.log_level, Arc::clone(&self.log_sink))
+    }
+
+    /// Proxy server listener releasing [`std::net::TcpListener`] via thread pool,
+    /// blocks until shutdown when workers are joined
+    pub fn bind(self, cb: Option<ChangeTarget>) -> Result<()> {
+        self.spawn(cb)?.join()
+    }
 
-        let _log = Log::new(&self.log_level);
-        println!(
-            "Listening: {}; Target: {}; Chunk size: {}KB; Log level: {:?}",
-            &self.address, &self.target, CHUNK_SIZE, &self.log_level
+    /// Bind listeners and serve connections in background thread,
+    /// errors of listeners and certificates are returned before it is started
+    pub fn spawn(self, cb: Option<ChangeTarget>) -> Result<ServerHandle> {
+        let listener = TcpListener::bind(self.address)?;
+        let local_addr = listener.local_addr()?;
+        self.shutdown.register(local_addr);
+
+        const TAG: &str = "Bind";
+        let _log = self.log();
+        let group = match &self.targets {
+            Some(targets) => targets.clone(),
+            None => UpstreamGroup::new(vec![Upstream::new(self.target)]),
+        };
+        _log.println(
+            LogLevel::Info,
+            TAG,
+            format!(
+                "Listening: {}; Chunk size: {}KB; Log level: {:?}; Targets",
+                local_addr, CHUNK_SIZE, &self.log_level
+            ),
+            group
+                .upstreams
+                .iter()
+                .map(|u| u.address.as_str())
+                .collect::<Vec<&str>>(),
         );
 
-        let pool = ThreadPool::new(self.threads);
-        for stream in listener.incoming() {
-            if let Err(err) = stream {
-                println!("Error in incoming stream {:?}", err);
-                continue;
+        let balancer = Arc::new(Balancer::new(group));
+        let router = Arc::new(Router::new(&self.routes, Arc::clone(&balancer)));
+        let metrics = Arc::new(Metrics::new());
+        let connections = Arc::new(Connections::new());
+        let mut admin = None;
+        let admin_addr = match self.admin_address {
+            Some(admin_address) => {
+                let admin_listener = TcpListener::bind(admin_address)?;
+                let admin_addr = admin_listener.local_addr()?;
+                _log.println(LogLevel::Info, TAG, "Admin listening", admin_addr);
+                let mut server = Admin::new(
+                    Arc::clone(&metrics),
+                    Arc::clone(&router),
+                    Arc::clone(&connections),
+                    _log.clone(),
+                );
+                if let Some(token) = &self.admin_token {
+                    server = server.with_token(token);
+                }
+                admin = Some(server.spawn(admin_listener, self.shutdown.clone()));
+                Some(admin_addr)
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "tls")]
+        let connector = Connector::with_tls(&self.upstream_tls.clone().unwrap_or_default())?;
+        #[cfg(not(feature = "tls"))]
+        let connector = Connector::default();
+        let connector = Arc::new(connector.with_connect_timeout(self.timeouts.connect));
+        let handler = Handler {
+            config: Arc::new(self.clone()),
+            pool: Arc::new(Pool::new(self.pool).with_connector(Arc::clone(&connector))),
+            router: Arc::clone(&router),
+            metrics: Arc::clone(&metrics),
+            log: _log.clone(),
+            access_log: match &self.access_log {
+                Some(access_log) => Some(Arc::new(access_log.open()?)),
+                None => None,
+            },
+            keep_alive: Arc::new(KeepAlive::new()),
+            tunnels: Arc::new(AtomicUsize::new(0)),
+            cache: match &self.cache {
+                Some(cache) => Some(Arc::new(cache.open()?)),
+                None => None,
+            },
+            #[cfg(feature = "tls")]
+            tls: match &self.tls {
+                Some(tls) => Some(tls.server_config()?),
+                None => None,
+            },
+        };
+        let health = self.health_check.as_ref().map(|health_check| {
+            health_check.clone().spawn(
+                router.balancers(),
+                connector,
+                _log.clone(),
+                self.shutdown.clone(),
+            )
+        });
+
+        let shutdown = self.shutdown.clone();
+        let server = Server {
+            pool: ThreadPool::new(self.threads, _log.clone()),
+            config: self,
+            listener,
+            handler,
+            balancer,
+            metrics,
+            connections,
+            admin,
+            health,
+            cb,
+            log: _log,
+        };
+        Ok(ServerHandle {
+            local_addr,
+            admin_addr,
+            shutdown,
+            thread: thread::spawn(move || server.run()),
+        })
+    }
+}
+
+/// Running proxy server returned by [`Builder::spawn`]
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    admin_addr: Option<SocketAddr>,
+    shutdown: Shutdown,
+    thread: JoinHandle<Result<()>>,
+}
+
+impl ServerHandle {
+    /// Actual address of listener, port is assigned by system when `0` is used
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Actual address of admin listener
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_addr
+    }
+
+    /// Trigger graceful shutdown, call [`ServerHandle::join`] to wait for its end
+    pub fn shutdown(&self) {
+        self.shutdown.trigger();
+    }
+
+    /// Wait until server is stopped
+    pub fn join(self) -> Result<()> {
+        self.thread
+            .join()
+            .map_err(|_| Error::other("Proxy server thread panicked"))?
+    }
+}
+
+/// Accept loop of proxy server started by [`Builder::spawn`]
+struct Server {
+    config: Builder,
+    listener: TcpListener,
+    handler: Handler,
+    pool: ThreadPool,
+    balancer: Arc<Balancer>,
+    metrics: Arc<Metrics>,
+    connections: Arc<Connections>,
+    /// Thread of admin listener, it is stopped by the same shutdown
+    admin: Option<JoinHandle<()>>,
+    /// Thread of health checks, it is stopped by the same shutdown
+    health: Option<JoinHandle<()>>,
+    cb: Option<ChangeTarget>,
+    log: Log,
+}
+
+impl Server {
+    /// Release connections to thread pool until shutdown, then wait for them
+    fn run(mut self) -> Result<()> {
+        const TAG: &str = "Bind";
+        let _log = self.log.clone();
+        let keep_alive = self.spawn_keep_alive();
+        for stream in self.listener.incoming() {
+            if self.config.shutdown.is_triggered() {
+                break;
             }
+            // Connection is registered before queue of pool, so shutdown waits for it too
+            let registered =
+                stream.and_then(|stream| Ok((self.connections.open(&stream)?, stream)));
+            let (connection, stream) = match registered {
+                Ok(registered) => registered,
+                Err(err) => {
+                    _log.println(LogLevel::Error, TAG, "Error in incoming stream", err);
+                    continue;
+                }
+            };
 
-            if let Some(func) = cb {
-                self.target = func(&self.target);
+            // Callback changes single target only, group of targets is managed by balancer
+            if let (Some(func), None) = (self.cb, &self.config.targets) {
+                let target = func(self.config.target);
+                if target != self.config.target {
+                    self.config.target = target;
+                    self.balancer.set_upstreams(vec![Upstream::new(target)]);
+                }
             }
-            let cl = Handler::new(self);
-            pool.execute(|| {
-                let stream = stream.unwrap();
-                let res = cl.handle_proxy(stream);
+            let cl = self.handler.clone();
+            let _log = _log.clone();
+            let metrics = Arc::clone(&self.metrics);
+            metrics.job_queued();
+            self.pool.execute(move || {
+                metrics.job_started();
+                metrics.connection_opened();
+                let res = cl.handle_proxy(stream, connection);
+                metrics.connection_closed();
                 if let Err(err) = res {
-                    println!("Error in handle proxy {:?}", err);
+                    _log.println(LogLevel::Error, TAG, "Error in handle proxy", err);
                 }
             });
         }
-        Err(Error::new(ErrorKind::Interrupted, "main thread crashed"))
+
+        let connections = &self.connections;
+        _log.println(
+            LogLevel::Info,
+            TAG,
+            "Shutdown, open connections",
+            connections.len(),
+        );
+        let deadline = Instant::now() + self.config.shutdown_timeout;
+        while !connections.is_empty() && Instant::now() < deadline {
+            connections.close_idle();
+            sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+        connections.close_all();
+        // Workers are joined when pool is dropped, thread of idle connections adds jobs to it
+        let _ = keep_alive.join();
+        drop(self.pool);
+        for thread in [self.admin, self.health].into_iter().flatten() {
+            let _ = thread.join();
+        }
+        _log.println(LogLevel::Info, TAG, "Stopped", self.listener.local_addr()?);
+        Ok(())
+    }
+
+    /// Return idle connections with data of next request to thread pool until shutdown
+    fn spawn_keep_alive(&self) -> JoinHandle<()> {
+        const TAG: &str = "Keep alive";
+        let handler = self.handler.clone();
+        let spawner = self.pool.spawner();
+        let metrics = Arc::clone(&self.metrics);
+        let shutdown = self.config.shutdown.clone();
+        let timeout = self.config.timeouts.idle;
+        let _log = self.log.clone();
+        let mut interval = KEEP_ALIVE_POLL_INTERVAL;
+        thread::spawn(move || loop {
+            let keep_alive = &handler.keep_alive;
+            let (ready, closed) = keep_alive.poll(timeout);
+            // Checks are less frequent while connections are idle
+            interval = match ready.is_empty() {
+                true => (interval * 2).min(KEEP_ALIVE_MAX_POLL_INTERVAL),
+                false => KEEP_ALIVE_POLL_INTERVAL,
+            };
+            for _ in 0..closed {
+                _log.println(LogLevel::Info, TAG, "idle connection closed", timeout);
+                metrics.connection_closed();
+            }
+            for parked in ready {
+                let cl = handler.clone();
+                let _log = _log.clone();
+                let metrics = Arc::clone(&metrics);
+                metrics.job_queued();
+                spawner.execute(move || {
+                    metrics.job_started();
+                    let res = cl.handle_requests(parked.client, parked.connection);
+                    metrics.connection_closed();
+                    if let Err(err) = res {
+                        _log.println(LogLevel::Error, TAG, "Error in handle proxy", err);
+                    }
+                });
+            }
+            if shutdown.is_triggered() && keep_alive.is_empty() {
+                break;
+            }
+            if keep_alive.wait(interval) {
+                interval = KEEP_ALIVE_POLL_INTERVAL;
+            }
+        })
     }
 }
 
-struct Handler {
-    config: Builder,
+/// State of one request kept until it is counted in metrics and access log
+struct Exchange {
+    started: Instant,
+    method: Method,
+    route: Option<String>,
+    entry: Option<AccessEntry>,
 }
 
-impl Handler {
-    fn new(config: Builder) -> Self {
-        Self { config }
+/// Place of open tunnel, it is freed when dropped
+struct TunnelGuard(Arc<AtomicUsize>);
+
+impl Drop for TunnelGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
+}
+
+#[derive(Clone)]
+struct Handler {
+    config: Arc<Builder>,
+    pool: Arc<Pool>,
+    router: Arc<Router>,
+    metrics: Arc<Metrics>,
+    /// Log of listener, its level is changed by admin API
+    log: Log,
+    access_log: Option<Arc<AccessLogger>>,
+    /// Client connections waiting for next request
+    keep_alive: Arc<KeepAlive>,
+    /// Count of open tunnels, every one holds thread of pool until it is closed
+    tunnels: Arc<AtomicUsize>,
+    cache: Option<Arc<CacheStore>>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
 
-    fn handle_proxy(self, client: TcpStream) -> Result<()> {
+impl Handler {
+    /// Serve requests of one client connection while both sides keep it alive
+    fn handle_proxy(self, client: TcpStream, connection: ConnectionGuard) -> Result<()> {
         const TAG: &str = "Handle proxy";
-        let _log = Log::new(&self.config.log_level);
+        let _log = self.log.clone();
 
         _log.println(LogLevel::Info, TAG, "client", &client);
 
+        // Handshakes of PROXY protocol and TLS are limited like request headers
+        let timeouts = self.config.timeouts;
+        client.set_read_timeout(Some(timeouts.header))?;
+        client.set_write_timeout(Some(timeouts.body))?;
         let mut client = Http::from(client);
+        if self.config.proxy_protocol {
+            client.accept_proxy_protocol()?;
+            connection.set_client(client.peer_addr()?);
+        }
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            client.accept_tls(Arc::clone(tls))?;
+        }
+        self.handle_requests(client, connection)
+    }
 
-        let head_client_buf = client.read_headers()?;
+    /// Serve requests of connection, it is parked while it waits for next request
+    fn handle_requests(self, mut client: Http, connection: ConnectionGuard) -> Result<()> {
+        const TAG: &str = "Handle proxy";
+        let _log = self.log.clone();
+        let timeouts = self.config.timeouts;
 
-        let error = client.socket.take_error().unwrap();
-        let error = match error {
-            None => "".to_string(),
-            Some(val) => val.to_string(),
+        let mut served = false;
+        loop {
+            connection.idle();
+            // Parked connection stays open, so it is counted until it is closed
+            if served && !client.is_readable() {
+                self.metrics.connection_opened();
+                self.keep_alive.park(client, connection);
+                return Ok(());
+            }
+            served = true;
+            let head_client_buf = match self.read_request_head(&mut client) {
+                Err(e) if is_timeout(&e) => {
+                    _log.println(LogLevel::Warn, TAG, "Request timeout", &client);
+                    return Handler::write_status(&mut client, 408);
+                }
+                Err(e) => match LimitError::status(&e) {
+                    Some(code) => {
+                        _log.println(LogLevel::Warn, TAG, "Rejected request", &e);
+                        return Handler::write_status(&mut client, code);
+                    }
+                    None => return Err(e),
+                },
+                Ok(head) => head,
+            };
+            if head_client_buf.is_empty() {
+                _log.println(LogLevel::Info, TAG, "client closed connection", &client);
+                break;
+            }
+            client.socket.set_read_timeout(Some(timeouts.body))?;
+
+            let mut req_client = match Request::new(Handler::get_socket(&client), head_client_buf) {
+                Err(e) if LimitError::status(&e).is_some() => {
+                    _log.println(LogLevel::Warn, TAG, "Rejected request", &e);
+                    return Handler::write_status(&mut client, 400);
+                }
+                res => res?,
+            };
+            connection.request();
+
+            _log.println(LogLevel::Info, TAG, "client request", &req_client);
+
+            let client_ip = client.peer_addr()?.ip();
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!(
+                "request",
+                method = %req_client.method,
+                target = %req_client.uri,
+                client_ip = %client_ip,
+                upstream = tracing::field::Empty,
+                request_id = tracing::field::Empty,
+            )
+            .entered();
+            let mut exchange = Exchange {
+                started: Instant::now(),
+                method: req_client.method.clone(),
+                route: None,
+                entry: self
+                    .access_log
+                    .as_ref()
+                    .map(|_| AccessEntry::new(&req_client, &client_ip.to_string())),
+            };
+
+            let request_target = req_client.uri.to_string();
+            let forward = match &self.config.forward {
+                Some(forward) if req_client.method == Method::Connect => {
+                    return self.handle_connect(client, &request_target, forward, exchange, &_log);
+                }
+                Some(forward) => ForwardProxy::parse_absolute(&request_target)
+                    .map(|destination| (forward, destination)),
+                None => None,
+            };
+
+            // Destinations of forward proxy are chosen by clients, so they share one label of metrics
+            let forwarded = forward.is_some();
+            // Selected target is kept until end of exchange to count active connections
+            let mut selected = None;
+            let mut route_name = None;
+            let target = match forward {
+                Some((forward, (destination, path))) => {
+                    if !forward.is_allowed(&destination) {
+                        _log.println(LogLevel::Warn, TAG, "Forbidden destination", &destination);
+                        return self.write_error(&mut client, exchange, 403);
+                    }
+                    req_client.set_target(&path)?;
+                    req_client.headers = req_client.headers.remove_header("Proxy-Connection")?;
+                    destination.address()
+                }
+                None => {
+                    let (route, balancer) = self.router.route(&req_client);
+                    route_name = route.and_then(|r| r.name.clone());
+                    if let Some(target) = route.and_then(|r| r.rewrite(&req_client)) {
+                        _log.println(LogLevel::Info, TAG, "rewrite target", &target);
+                        req_client.set_target(&target)?;
+                    }
+                    match balancer.select(&req_client) {
+                        Some(s) => selected.insert(s).address().to_string(),
+                        None => {
+                            _log.println(
+                                LogLevel::Warn,
+                                TAG,
+                                "No targets for request",
+                                &req_client.uri,
+                            );
+                            return self.write_error(&mut client, exchange, 503);
+                        }
+                    }
+                }
+            };
+            let target = target.as_str();
+            exchange.route = route_name.clone();
+            let vars = Variables {
+                client_ip: client_ip.to_string(),
+                request_id: req_client
+                    .headers
+                    .get_header("X-Request-Id")
+                    .unwrap_or_else(Variables::new_request_id),
+                upstream: target.to_string(),
+                route: route_name,
+                method: req_client.method.clone(),
+            };
+            if let Some(entry) = exchange.entry.as_mut() {
+                entry.request_id = vars.request_id.clone();
+            }
+            #[cfg(feature = "tracing")]
+            _span
+                .record("upstream", target)
+                .record("request_id", vars.request_id.as_str());
+
+            // Response is looked up by request as it is received from client,
+            // which is kept to match `Vary` of response stored later
+            let cache = self.cache.as_ref().map(|cache| {
+                let key = CacheStore::key(&req_client);
+                let lookup = cache.lookup(&key, &req_client);
+                (cache, key, lookup, req_client.clone())
+            });
+            if let Some((_, _, Lookup::Hit(cached), _)) = &cache {
+                _log.println(LogLevel::Info, TAG, "cache hit", &req_client.uri);
+                match self.write_cached(&mut client, &req_client, cached, "HIT", &vars, exchange)? {
+                    true => continue,
+                    false => break,
+                }
+            }
+            if let Some(entry) = exchange.entry.as_mut() {
+                entry.upstream = target.to_string();
+            }
+            let host = match Target::parse(target) {
+                Ok(t) => t.host_header(),
+                Err(e) => {
+                    _log.println(LogLevel::Warn, TAG, "Failed proxy", &e);
+                    return self.write_error(&mut client, exchange, 502);
+                }
+            };
+            let proto = match client.is_tls() {
+                true => "https",
+                false => "http",
+            };
+            req_client.headers = self.config.forwarded_headers.apply(
+                &req_client.headers,
+                &client_ip,
+                proto,
+                req_client.headers.get_header("Host").as_deref(),
+            )?;
+            req_client.change_host(&host)?;
+
+            let rules = &self.config.header_rules;
+            req_client.headers =
+                HeaderRule::apply_all(rules, Direction::Request, &req_client.headers, &vars, None)?;
+            if let Some((_, _, Lookup::Stale(cached), _)) = &cache {
+                req_client.headers = cached.conditional(&req_client.headers)?;
+            }
+
+            // Place of tunnel is taken before upgrade request is sent, it is kept if target accepts it
+            let mut _tunnel = None;
+            if req_client.headers.upgrade() {
+                match self.open_tunnel() {
+                    Some(tunnel) => _tunnel = Some(tunnel),
+                    None => {
+                        _log.println(LogLevel::Warn, TAG, "Too many tunnels", &req_client.uri);
+                        return self.write_error(&mut client, exchange, 503);
+                    }
+                }
+            }
+
+            // Connection with PROXY protocol header belongs to one client, so it is never reused
+            let upstream_proxy = self.config.upstream_proxy_protocol;
+            let http = match upstream_proxy {
+                Some(_) => self.pool.connect(target),
+                None => self.pool.get(target),
+            };
+            if let Err(e) = &http {
+                _log.println(LogLevel::Warn, TAG, "Failed proxy", e);
+                if e.kind() != ErrorKind::WouldBlock {
+                    self.metrics.connect_error(match forwarded {
+                        true => FORWARD_UPSTREAM,
+                        false => target,
+                    });
+                }
+                let code = match e.kind() {
+                    ErrorKind::WouldBlock => 503,
+                    ErrorKind::TimedOut => 504,
+                    _ => 502,
+                };
+                return self.write_error(&mut client, exchange, code);
+            }
+            let mut http = http?;
+            if let Some(version) = upstream_proxy {
+                let header = ProxyHeader {
+                    source: client.peer_addr()?,
+                    destination: client.local_addr()?,
+                };
+                // Header goes before TLS handshake of target, which is started by first write of http
+                http.socket.write_all(&header.encode(version))?;
+            }
+
+            let mut h = self.send_request(&mut client, &mut http, &mut req_client, &_log)?;
+            // Idle upstream connection could be closed by target, retry only if body is not sent yet
+            let closed = matches!(&h, Ok((h, _)) if h.is_empty());
+            if closed && http.reused && Body::request(&req_client) == Body::Empty {
+                _log.println(LogLevel::Info, TAG, "reconnect to target", target);
+                http = self.pool.connect(target)?;
+                h = self.send_request(&mut client, &mut http, &mut req_client, &_log)?;
+            }
+            let (h, body_sent) = match h {
+                Ok((h, _)) if h.is_empty() => {
+                    _log.println(LogLevel::Warn, TAG, "Empty target response", &*http);
+                    return self.write_error(&mut client, exchange, 502);
+                }
+                Ok(h) => h,
+                Err(code) => {
+                    _log.println(LogLevel::Warn, TAG, "Failed exchange", code);
+                    return self.write_error(&mut client, exchange, code);
+                }
+            };
+
+            let mut req_http = Request::new(Handler::get_socket(&client), h)?;
+            _log.println(LogLevel::Info, TAG, "target response", &req_http);
+
+            // Framing is taken before rules, so they can't break forwarding of body
+            let status = req_http.headers.status()?.code;
+            let body = Body::response(&req_client, &req_http)?;
+            // Cache keeps response of target, rules are applied to every response sent from it
+            let res_target = cache.as_ref().map(|_| req_http.headers.clone());
+            req_http.headers = HeaderRule::apply_all(
+                rules,
+                Direction::Response,
+                &req_http.headers,
+                &vars,
+                Some(status),
+            )?;
+
+            // Switched protocol like WebSocket is forwarded as is in both directions
+            if status == 101 && req_client.headers.upgrade() {
+                client.write_all(req_http.headers.raw())?;
+                client.flush()?;
+                let (sent, received) = client.duplex(&mut http, self.config.timeouts.tunnel)?;
+                _log.println(LogLevel::Info, TAG, "upgrade closed, sent", sent);
+                _log.println(LogLevel::Info, TAG, "upgrade closed, received", received);
+                self.metrics.add_received(sent);
+                self.metrics.add_sent(received);
+                self.finish(exchange, status, received);
+                return Ok(());
+            }
+
+            let framed = body.is_framed();
+            let reusable = framed && body_sent && req_http.keep_alive && upstream_proxy.is_none();
+            let mut partial = None;
+            if let (Some((cache, key, lookup, req_cache)), Some(res_target)) = (&cache, &res_target)
+            {
+                if let (Lookup::Stale(cached), 304) = (lookup, status) {
+                    if reusable {
+                        http.release();
+                    }
+                    let cached = cache.revalidate(cached, res_target)?;
+                    match self.write_cached(
+                        &mut client,
+                        req_cache,
+                        &cached,
+                        "REVALIDATED",
+                        &vars,
+                        exchange,
+                    )? {
+                        true => continue,
+                        false => break,
+                    }
+                }
+                if !req_client.method.is_safe() && status < 400 {
+                    cache.invalidate(key);
+                }
+                // Body longer than max size of entry is not stored, its read part is written
+                // to client before the rest of it
+                if !matches!(lookup, Lookup::Bypass)
+                    && cache.is_storable(req_cache, status, res_target, &body)
+                {
+                    let (data, left) = http.read_framed_limited(&body, cache.max_entry_size())?;
+                    let left = match left {
+                        Some(left) => left,
+                        None => {
+                            if reusable {
+                                http.release();
+                            }
+                            let cached = cache.store(key, req_cache, res_target, data)?;
+                            match self.write_cached(
+                                &mut client,
+                                req_cache,
+                                &cached,
+                                "MISS",
+                                &vars,
+                                exchange,
+                            )? {
+                                true => continue,
+                                false => break,
+                            }
+                        }
+                    };
+                    partial = Some((data, left));
+                }
+                let state = match lookup {
+                    Lookup::Bypass => "BYPASS",
+                    _ => "MISS",
+                };
+                req_http.headers = req_http.headers.set_header("X-Cache", state)?;
+            }
+
+            // Connection header is hop-by-hop, so client side keeps its own persistence
+            let keep_alive =
+                framed && req_client.keep_alive && !self.config.shutdown.is_triggered();
+            req_http.headers = req_http.headers.set_header(
+                "Connection",
+                match keep_alive {
+                    true => "keep-alive",
+                    false => "close",
+                },
+            )?;
+            client.write_all(req_http.headers.raw())?;
+
+            let size = match partial {
+                Some((data, left)) => client.forward_rest(&mut http, &body, &data, left)?,
+                None => client.forward_body(&mut http, &body)?,
+            };
+            _log.println(LogLevel::Info, TAG, "response body size", size);
+            self.metrics.add_sent(size);
+            self.finish(exchange, status, size);
+
+            if reusable {
+                http.release();
+            }
+            if !keep_alive {
+                break;
+            }
+        }
+
+        client.close_notify()
+    }
+
+    /// Open tunnel to destination of `CONNECT` request and forward data until both sides close it
+    fn handle_connect(
+        &self,
+        mut client: Http,
+        authority: &str,
+        forward: &ForwardProxy,
+        mut exchange: Exchange,
+        _log: &Log,
+    ) -> Result<()> {
+        const TAG: &str = "Handle connect";
+        let destination = match Target::parse(authority) {
+            Ok(destination) if !authority.contains("://") => destination,
+            _ => {
+                _log.println(LogLevel::Warn, TAG, "Invalid destination", authority);
+                return self.write_error(&mut client, exchange, 400);
+            }
+        };
+        if !forward.is_allowed(&destination) {
+            _log.println(LogLevel::Warn, TAG, "Forbidden destination", authority);
+            return self.write_error(&mut client, exchange, 403);
+        }
+        let _tunnel = match self.open_tunnel() {
+            Some(tunnel) => tunnel,
+            None => {
+                _log.println(LogLevel::Warn, TAG, "Too many tunnels", authority);
+                return self.write_error(&mut client, exchange, 503);
+            }
         };
 
-        let mut req_client = Request::new(
-            Socket {
-                host: client.socket.local_addr().unwrap().to_string(),
-                peer_addr: client.socket.peer_addr().unwrap().to_string(),
-                ttl: client.socket.ttl().unwrap(),
-                error,
-            },
-            head_client_buf,
-        )?;
+        if let Some(entry) = exchange.entry.as_mut() {
+            entry.upstream = destination.authority();
+        }
+        let authority = destination.authority();
+        let mut http = match Http::connect_timeout(&authority, self.config.timeouts.connect) {
+            Ok(http) => http,
+            Err(e) => {
+                _log.println(LogLevel::Warn, TAG, "Failed connect", &e);
+                self.metrics.connect_error(FORWARD_UPSTREAM);
+                let code = match e.kind() {
+                    ErrorKind::TimedOut => 504,
+                    _ => 502,
+                };
+                return self.write_error(&mut client, exchange, code);
+            }
+        };
+        let status = Status {
+            code: 200,
+            text: "Connection Established".to_string(),
+        };
+        client.write_all(Headers::new_response(&status, vec![]).raw())?;
+        client.flush()?;
 
-        _log.println(LogLevel::Info, TAG, "client request", &req_client);
-        req_client.change_host(&self.config.target)?;
+        let (sent, received) = client.duplex(&mut http, self.config.timeouts.tunnel)?;
+        _log.println(LogLevel::Info, TAG, "tunnel closed, sent", sent);
+        _log.println(LogLevel::Info, TAG, "tunnel closed, received", received);
+        self.metrics.add_received(sent);
+        self.metrics.add_sent(received);
+        self.finish(exchange, 200, received);
+        Ok(())
+    }
 
-        let http = Http::connect(&self.config.target);
-        if let Err(e) = &http {
-            _log.println(LogLevel::Warn, TAG, "Failed proxy", e);
-            client.write(
-                Headers::new_response(&Status::new(502), vec![])
-                    .raw
-                    .as_bytes(),
+    /// Write response from cache with response rules, its age and state of cache in `X-Cache`,
+    /// returns whether connection to client is kept alive
+    fn write_cached(
+        &self,
+        client: &mut Http,
+        req_client: &Request,
+        cached: &Cached,
+        state: &str,
+        vars: &Variables,
+        exchange: Exchange,
+    ) -> Result<bool> {
+        let keep_alive = req_client.keep_alive && !self.config.shutdown.is_triggered();
+        let status = cached.headers.status()?.code;
+        let headers = HeaderRule::apply_all(
+            &self.config.header_rules,
+            Direction::Response,
+            &cached.headers,
+            vars,
+            Some(status),
+        )?;
+        let headers = headers
+            .set_header("Age", &cached.age.as_secs().to_string())?
+            .set_header("X-Cache", state)?
+            .set_header(
+                "Connection",
+                match keep_alive {
+                    true => "keep-alive",
+                    false => "close",
+                },
             )?;
-            client.flush()?;
-            sleep(Duration::from_millis(100));
-            return Ok(());
+        client.write_all(headers.raw())?;
+        let size = match req_client.method {
+            Method::Head => 0,
+            _ => {
+                client.write_all(&cached.body)?;
+                cached.body.len()
+            }
+        };
+        client.flush()?;
+        self.metrics.add_sent(size);
+        self.finish(exchange, status, size);
+        Ok(keep_alive)
+    }
+
+    /// Take place of `CONNECT` tunnel or upgraded connection, they hold threads of pool,
+    /// so at least one thread is kept for other requests
+    fn open_tunnel(&self) -> Option<TunnelGuard> {
+        let max = self.config.threads.saturating_sub(1).max(1);
+        self.tunnels
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(TunnelGuard(Arc::clone(&self.tunnels)))
+    }
+
+    /// Write status of failed exchange to client and log it
+    fn write_error(&self, client: &mut Http, exchange: Exchange, code: u16) -> Result<()> {
+        self.finish(exchange, code, 0);
+        Handler::write_status(client, code)
+    }
+
+    /// Count completed exchange in metrics and write its entry to access log
+    fn finish(&self, exchange: Exchange, status: u16, bytes_sent: usize) {
+        self.metrics.observe_request(
+            &exchange.method,
+            status,
+            exchange.route.as_deref().unwrap_or(""),
+            exchange.started.elapsed(),
+        );
+        if let (Some(access_log), Some(mut entry)) = (&self.access_log, exchange.entry) {
+            entry.finish(status, bytes_sent);
+            if let Err(err) = access_log.write(&entry) {
+                self.log
+                    .println(LogLevel::Error, "Access log", "Error in access log", err);
+            }
+        }
+    }
+
+    /// Read request headers of client within timeouts and check them by limits
+    fn read_request_head(&self, client: &mut Http) -> Result<Vec<u8>> {
+        let Builder {
+            timeouts, limits, ..
+        } = self.config.as_ref();
+        let head =
+            client.read_headers_timeout(timeouts.idle, timeouts.header, limits.header_size)?;
+        // Empty head means that client closed connection without request
+        if !head.is_empty() {
+            limits.check(&String::from_utf8_lossy(&head))?;
         }
-        let mut http = http?;
+        Ok(head)
+    }
 
-        http.write(req_client.headers.raw.as_bytes())?;
+    /// Write request headers and body to target and read final response headers,
+    /// returns them with flag of forwarded body.
+    /// Exceeded timeout or limit is returned as status for client:
+    /// `408` of reading request body, `413` of its size or `504` of waiting for target
+    fn send_request(
+        &self,
+        client: &mut Http,
+        http: &mut Http,
+        req_client: &mut Request,
+        _log: &Log,
+    ) -> Result<std::result::Result<(Vec<u8>, bool), u16>> {
+        const TAG: &str = "Send request";
+        let timeout = Some(self.config.timeouts.response);
+        http.socket.set_read_timeout(timeout)?;
+        http.socket.set_write_timeout(timeout)?;
+        match http.write_all(req_client.headers.raw()) {
+            Err(e) if is_timeout(&e) => return Ok(Err(504)),
+            res => res?,
+        }
 
-        if req_client.content_length != 0 {
-            let body = client.read_body(&req_client)?;
-            _log.println(
-                LogLevel::Info,
-                TAG,
-                "request body",
-                str::from_utf8(&body).unwrap(),
-            );
-            http.write(&body)?;
+        // Client expecting `100 Continue` doesn't send body until it gets it from target,
+        // target which never sends it gets body after short wait. Data of TLS socket
+        // can't be peeked, so body goes to TLS targets at once
+        let body = Body::request(req_client);
+        let expect = body != Body::Empty
+            && !http.is_tls()
+            && req_client
+                .headers
+                .header_map()
+                .get("Expect")
+                .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"));
+        if expect {
+            http.socket
+                .set_read_timeout(Some(EXPECT_CONTINUE_TIMEOUT))?;
+            let answered = match http.socket.peek(&mut [0; 1]) {
+                Err(e) if is_timeout(&e) => false,
+                res => res.map(|_| true)?,
+            };
+            http.socket.set_read_timeout(timeout)?;
+            if answered {
+                match self.read_response_head(client, http, req_client, _log)? {
+                    Ok(None) => {}
+                    // Final response refuses body, client may still send it,
+                    // so both connections are closed after response
+                    Ok(Some(head)) => {
+                        req_client.keep_alive = false;
+                        return Ok(Ok((head, false)));
+                    }
+                    Err(code) => return Ok(Err(code)),
+                }
+            }
         }
 
-        let h = http.read_headers()?;
+        let forwarded = match self.config.limits.body_size {
+            Some(max_size) => http.forward_body_limited(client, &body, max_size),
+            None => http.forward_body(client, &body),
+        };
+        let size = match forwarded {
+            Err(e) if is_timeout(&e) => return Ok(Err(408)),
+            Err(e) => match LimitError::status(&e) {
+                Some(code) => return Ok(Err(code)),
+                None => return Err(e),
+            },
+            Ok(size) => size,
+        };
+        _log.println(LogLevel::Info, TAG, "request body size", size);
+        self.metrics.add_received(size);
+
+        loop {
+            match self.read_response_head(client, http, req_client, _log)? {
+                Ok(None) => {}
+                Ok(Some(head)) => return Ok(Ok((head, true))),
+                Err(code) => return Ok(Err(code)),
+            }
+        }
+    }
 
+    /// Read response headers of target, interim response like `100 Continue` is forwarded
+    /// to client and `None` is returned for it, clients of `HTTP/1.0` don't expect them
+    fn read_response_head(
+        &self,
+        client: &mut Http,
+        http: &mut Http,
+        req_client: &Request,
+        _log: &Log,
+    ) -> Result<std::result::Result<Option<Vec<u8>>, u16>> {
+        const TAG: &str = "Send request";
+        let head = match http.read_headers() {
+            Err(e) if is_timeout(&e) => return Ok(Err(504)),
+            res => res?,
+        };
+        let code = Head::parse(&head).status_line().map(|l| l.code);
+        if !matches!(code, Some(100..=199)) || code == Some(101) {
+            return Ok(Ok(Some(head)));
+        }
+        _log.println(LogLevel::Info, TAG, "interim response", code);
+        if req_client.version != Version::Http10 {
+            client.write_all(&head)?;
+            client.flush()?;
+        }
+        Ok(Ok(None))
+    }
+
+    fn write_status(client: &mut Http, code: u16) -> Result<()> {
+        client.write_all(Headers::new_response(&Status::new(code), vec![]).raw())?;
+        client.flush()?;
+        sleep(Duration::from_millis(100));
+        Ok(())
+    }
+
+    fn get_socket(client: &Http) -> Socket {
         let error = client.socket.take_error().unwrap();
         let error = match error {
             None => "".to_string(),
             Some(val) => val.to_string(),
         };
 
-        let req_http = Request::new(
-            Socket {
-                host: client.socket.local_addr().unwrap().to_string(),
-                peer_addr: client.socket.peer_addr().unwrap().to_string(),
-                ttl: client.socket.ttl().unwrap(),
-                error,
-            },
-            h.clone(),
-        )?;
-        _log.println(LogLevel::Info, TAG, "target response", &req_http);
-        client.write(req_http.headers.raw.as_bytes())?;
-        client.tunnel(&mut http, &_log)?;
-
-        Ok(())
+        Socket {
+            host: client.local_addr().unwrap().to_string(),
+            peer_addr: client.peer_addr().unwrap().to_string(),
+            ttl: client.socket.ttl().unwrap(),
+            error,
+        }
     }
 }
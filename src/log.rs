@@ -1,32 +1,251 @@
-#[derive(Debug, Clone, Copy)]
+//! Module [`Log`].
+//! Messages filtered by [`LogLevel`] and written to pluggable [`LogSink`]
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{Error, ErrorKind, Result, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LogLevel {
+    Trace,
+    Debug,
     Info,
     Warn,
     Error,
 }
 
-/// Log level [`Info`, `Warn`, `Error`]
+/// Log level [`Trace`, `Debug`, `Info`, `Warn`, `Error`]
 pub const LOG_LEVEL: LogLevel = LogLevel::Info;
 
 impl LogLevel {
     fn as_num(&self) -> u8 {
         use LogLevel::*;
         match &self {
-            Info => 0,
-            Warn => 1,
-            Error => 2,
+            Trace => 0,
+            Debug => 1,
+            Info => 2,
+            Warn => 3,
+            Error => 4,
+        }
+    }
+
+    fn from_num(num: u8) -> Self {
+        use LogLevel::*;
+        match num {
+            0 => Trace,
+            1 => Debug,
+            2 => Info,
+            3 => Warn,
+            _ => Error,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = Error;
+
+    /// Parse case insensitive name of level
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "trace" => Ok(LogLevel::Trace),
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown log level {}", s),
+            )),
+        }
+    }
+}
+
+/// Destination of log messages
+pub trait LogSink: fmt::Debug + Send + Sync {
+    /// Write message which is passed filter of level
+    fn write(&self, level: LogLevel, tag: &str, message: &str);
+}
+
+/// Sink printing messages to stdout, it is used by default
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn write(&self, level: LogLevel, tag: &str, message: &str) {
+        println!("[ {:?} ] <{}> {}", level, tag, message);
+    }
+}
+
+/// Sink printing messages to stderr
+#[derive(Debug, Default)]
+pub struct StderrSink;
+
+impl LogSink for StderrSink {
+    fn write(&self, level: LogLevel, tag: &str, message: &str) {
+        eprintln!("[ {:?} ] <{}> {}", level, tag, message);
+    }
+}
+
+/// Sink appending messages to file
+#[derive(Debug)]
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl LogSink for FileSink {
+    fn write(&self, level: LogLevel, tag: &str, message: &str) {
+        let line = format!("[ {:?} ] <{}> {}\n", level, tag, message);
+        let _ = self.file.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+/// Sink keeping messages in memory, useful for tests
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    lines: Mutex<Vec<String>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Written messages like `[ Info ] <Tag> message`
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+}
+
+impl LogSink for MemorySink {
+    fn write(&self, level: LogLevel, tag: &str, message: &str) {
+        let line = format!("[ {:?} ] <{}> {}", level, tag, message);
+        self.lines.lock().unwrap().push(line);
+    }
+}
+
+/// Sink sending messages to local syslog by UNIX datagram socket like `/dev/log`
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct SyslogSink {
+    socket: std::os::unix::net::UnixDatagram,
+    ident: String,
+}
+
+#[cfg(unix)]
+impl SyslogSink {
+    /// Connect to syslog socket, messages are marked by `ident` like `proxy-server`
+    pub fn connect(path: &str, ident: &str) -> Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self {
+            socket,
+            ident: ident.to_string(),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl LogSink for SyslogSink {
+    fn write(&self, level: LogLevel, tag: &str, message: &str) {
+        let severity = match level {
+            LogLevel::Error => 3,
+            LogLevel::Warn => 4,
+            LogLevel::Info => 6,
+            LogLevel::Debug | LogLevel::Trace => 7,
+        };
+        // Facility user
+        let priority = 8 + severity;
+        let line = format!("<{}>{}: <{}> {}", priority, self.ident, tag, message);
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+/// Sink forwarding messages to facade of crate `log`, enabled by feature `log`
+#[cfg(feature = "log")]
+#[derive(Debug, Default)]
+pub struct LogFacadeSink;
+
+#[cfg(feature = "log")]
+impl LogSink for LogFacadeSink {
+    fn write(&self, level: LogLevel, tag: &str, message: &str) {
+        let level = match level {
+            LogLevel::Trace => log_facade::Level::Trace,
+            LogLevel::Debug => log_facade::Level::Debug,
+            LogLevel::Info => log_facade::Level::Info,
+            LogLevel::Warn => log_facade::Level::Warn,
+            LogLevel::Error => log_facade::Level::Error,
+        };
+        log_facade::log!(target: "proxy_server", level, "<{}> {}", tag, message);
+    }
+}
+
+/// Sink forwarding messages to events of crate `tracing` inside of request spans,
+/// enabled by feature `tracing`
+#[cfg(feature = "tracing")]
+#[derive(Debug, Default)]
+pub struct TracingSink;
+
+#[cfg(feature = "tracing")]
+impl LogSink for TracingSink {
+    fn write(&self, level: LogLevel, tag: &str, message: &str) {
+        match level {
+            LogLevel::Trace => tracing::trace!(tag, "{}", message),
+            LogLevel::Debug => tracing::debug!(tag, "{}", message),
+            LogLevel::Info => tracing::info!(tag, "{}", message),
+            LogLevel::Warn => tracing::warn!(tag, "{}", message),
+            LogLevel::Error => tracing::error!(tag, "{}", message),
         }
     }
 }
 
+/// Log with level shared by its clones, so it can be changed at runtime
 #[derive(Clone)]
-pub struct Log<'a> {
-    level: &'a LogLevel,
+pub struct Log {
+    level: Arc<AtomicU8>,
+    sink: Arc<dyn LogSink>,
 }
 
-impl<'a> Log<'a> {
+impl Log {
+    /// Create log printing to stdout
     pub fn new(level: &LogLevel) -> Log {
-        Log { level }
+        Log::with_sink(level, Arc::new(StdoutSink))
+    }
+
+    pub fn with_sink(level: &LogLevel, sink: Arc<dyn LogSink>) -> Log {
+        Log {
+            level: Arc::new(AtomicU8::new(level.as_num())),
+            sink,
+        }
+    }
+
+    /// Current level of log
+    pub fn level(&self) -> LogLevel {
+        LogLevel::from_num(self.level.load(Ordering::Relaxed))
+    }
+
+    /// Change level of log and all its clones
+    pub fn set_level(&self, level: LogLevel) {
+        self.level.store(level.as_num(), Ordering::Relaxed);
+    }
+
+    /// Check that messages of level are written
+    pub fn is_enabled(&self, level: LogLevel) -> bool {
+        self.level.load(Ordering::Relaxed) <= level.as_num()
     }
 
     pub fn println<T, K>(&self, level: LogLevel, tag: &str, msg: K, arg: T)
@@ -34,8 +253,8 @@ impl<'a> Log<'a> {
         T: std::fmt::Debug,
         K: std::fmt::Display,
     {
-        if self.level.as_num() <= level.as_num() {
-            println!("[ {:?} ] <{}> {}: {:?}", level, tag, msg, arg);
+        if self.is_enabled(level) {
+            self.sink.write(level, tag, &format!("{}: {:?}", msg, arg));
         }
     }
 }
@@ -1,16 +1,37 @@
-use proxy_server::{log::LogLevel, prelude::target, Builder, ChangeTarget};
-use std::thread::spawn;
+use proxy_server::{log::LogLevel, prelude::target, shutdown::Shutdown, Builder, ChangeTarget};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    flag,
+};
+use std::{
+    io::Result,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+/// Flag which is set on SIGINT and SIGTERM
+fn handle_signals() -> Result<Arc<AtomicBool>> {
+    let signaled = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        flag::register(signal, Arc::clone(&signaled))?;
+    }
+    Ok(signaled)
+}
 
 #[allow(dead_code)]
 fn get_dynamic_target(old: &'static str) -> &'static str {
     let target1 = "127.0.0.1:3001";
     let target2 = "127.0.0.1:3003";
-    let res = match old {
+
+    match old {
         "127.0.0.1:3001" => target2,
         "127.0.0.1:3003" => target1,
         _ => target1,
-    };
-    res
+    }
 }
 
 fn get_static_target(old: &'static str) -> &'static str {
@@ -24,11 +45,23 @@ fn main() {
     spawn(move || {
         target(ra).expect("Error in target");
     });
+
+    let shutdown = Shutdown::new();
+    let signaled = handle_signals().expect("Error in signal handlers");
+    let trigger = shutdown.clone();
+    spawn(move || {
+        while !signaled.load(Ordering::SeqCst) {
+            sleep(Duration::from_millis(100));
+        }
+        trigger.trigger();
+    });
+
     Builder::new()
         .with_address("127.0.0.1:3000")
         .with_target(ra)
         .with_log_level(LogLevel::Info)
         .with_threads(4)
+        .with_shutdown(shutdown)
         .bind(Some(cb))
         .expect("Error in proxy");
 }
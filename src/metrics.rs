@@ -0,0 +1,237 @@
+//! Module [`Metrics`].
+//! Counters and histograms of proxy server in Prometheus text format
+use crate::http::method::Method;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Upper bounds of request duration buckets in seconds
+pub const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Label of target connect errors of forward proxy and `CONNECT` tunnels,
+/// their destinations are chosen by clients
+pub const FORWARD_UPSTREAM: &str = "forward";
+
+/// Counts of observed values by buckets
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (i, bound) in DURATION_BUCKETS.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[i] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Metrics shared by connection handlers
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Count of requests by method, status and route
+    requests: Mutex<BTreeMap<(String, u16, String), u64>>,
+    /// Duration of requests by route
+    durations: Mutex<BTreeMap<String, Histogram>>,
+    /// Failed connections by target
+    connect_errors: Mutex<BTreeMap<String, u64>>,
+    received_bytes: AtomicU64,
+    sent_bytes: AtomicU64,
+    active_connections: AtomicI64,
+    queued_jobs: AtomicI64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count completed request, route is empty for requests without named route.
+    /// Extension methods are counted as `OTHER`, so clients can't add series
+    pub fn observe_request(&self, method: &Method, status: u16, route: &str, duration: Duration) {
+        let method = match method {
+            Method::Extension(_) => "OTHER",
+            method => method.as_str(),
+        };
+        let key = (method.to_string(), status, route.to_string());
+        *self.requests.lock().unwrap().entry(key).or_default() += 1;
+        self.durations
+            .lock()
+            .unwrap()
+            .entry(route.to_string())
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Count failed connection to target, `upstream` is configured target or [`FORWARD_UPSTREAM`]
+    pub fn connect_error(&self, upstream: &str) {
+        *self
+            .connect_errors
+            .lock()
+            .unwrap()
+            .entry(upstream.to_string())
+            .or_default() += 1;
+    }
+
+    /// Count bytes of bodies and tunnels received from clients
+    pub fn add_received(&self, bytes: usize) {
+        self.received_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Count bytes of bodies and tunnels sent to clients
+    pub fn add_sent(&self, bytes: usize) {
+        self.sent_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn connection_opened(&self) {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Count of open client connections
+    pub fn active_connections(&self) -> i64 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Connection is waiting for free thread of pool
+    pub fn job_queued(&self) {
+        self.queued_jobs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Connection is taken by thread of pool
+    pub fn job_started(&self) {
+        self.queued_jobs.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in Prometheus text format
+    pub fn render(&self) -> String {
+        let mut res = String::new();
+
+        header(
+            &mut res,
+            "proxy_requests_total",
+            "counter",
+            "Count of requests",
+        );
+        for ((method, status, route), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                res,
+                "proxy_requests_total{{method=\"{}\",status=\"{}\",route=\"{}\"}} {}",
+                escape(method),
+                status,
+                escape(route),
+                count
+            );
+        }
+
+        header(
+            &mut res,
+            "proxy_request_duration_seconds",
+            "histogram",
+            "Duration of requests",
+        );
+        for (route, histogram) in self.durations.lock().unwrap().iter() {
+            let route = escape(route);
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    res,
+                    "proxy_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bound, count
+                );
+            }
+            let _ = writeln!(
+                res,
+                "proxy_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, histogram.count
+            );
+            let _ = writeln!(
+                res,
+                "proxy_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route, histogram.sum
+            );
+            let _ = writeln!(
+                res,
+                "proxy_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, histogram.count
+            );
+        }
+
+        header(
+            &mut res,
+            "proxy_upstream_connect_errors_total",
+            "counter",
+            "Count of failed connections to targets",
+        );
+        for (upstream, count) in self.connect_errors.lock().unwrap().iter() {
+            let _ = writeln!(
+                res,
+                "proxy_upstream_connect_errors_total{{upstream=\"{}\"}} {}",
+                escape(upstream),
+                count
+            );
+        }
+
+        let values = [
+            (
+                "proxy_received_bytes_total",
+                "counter",
+                "Bytes of bodies and tunnels received from clients",
+                self.received_bytes.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "proxy_sent_bytes_total",
+                "counter",
+                "Bytes of bodies and tunnels sent to clients",
+                self.sent_bytes.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "proxy_active_connections",
+                "gauge",
+                "Count of open client connections",
+                self.active_connections(),
+            ),
+            (
+                "proxy_thread_pool_queue_depth",
+                "gauge",
+                "Count of client connections waiting for free thread",
+                self.queued_jobs.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, kind, help, value) in values {
+            header(&mut res, name, kind, help);
+            let _ = writeln!(res, "{} {}", name, value);
+        }
+        res
+    }
+}
+
+fn header(res: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(res, "# HELP {} {}", name, help);
+    let _ = writeln!(res, "# TYPE {} {}", name, kind);
+}
+
+/// Escape value of label
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 #[allow(unused_variables)]
 #[cfg(feature = "chunk_1KB")]
 pub const CHUNK_SIZE: usize = 1024;
@@ -48,3 +50,80 @@ pub const HTTP_VERSION_DEFAULT: &str = "HTTP/1.1";
 
 /// Default ttl
 pub const TTL_DEFAULT: u32 = 300;
+
+/// Max count of idle connections kept for one target
+pub const POOL_MAX_IDLE: usize = 16;
+
+/// Max count of connections to one target
+pub const POOL_MAX_PER_HOST: usize = 256;
+
+/// Time after which idle target connection is closed
+pub const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time of waiting for free connection when target limit is reached
+pub const POOL_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause between rounds of health checks
+pub const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Max time of one health check probe
+pub const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Count of failed health checks in a row after which target is ejected
+pub const HEALTH_UNHEALTHY_THRESHOLD: u32 = 3;
+
+/// Count of succeeded health checks in a row after which target is admitted again
+pub const HEALTH_HEALTHY_THRESHOLD: u32 = 2;
+
+/// Max time of connecting to target
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Max time of reading request headers since their first byte
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Max time without progress of reading request body or writing to client
+pub const BODY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Max time of waiting for data from target
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Max time of waiting for `100 Continue` of target before request body is sent
+pub const EXPECT_CONTINUE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Max time of waiting for next request on keep-alive connection
+pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause between checks of keep-alive connections waiting for next request,
+/// it is doubled while no connection has data
+pub const KEEP_ALIVE_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Max pause between checks of keep-alive connections, connection parked during pause
+/// is checked at once
+pub const KEEP_ALIVE_MAX_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Time without data in both directions after which tunnel is closed
+pub const TUNNEL_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Max time of waiting for data from one side of tunnel before checking other side
+pub const TUNNEL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Max time of finishing requests in progress after shutdown is triggered
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause between checks of open connections during shutdown
+pub const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Max size of request line and headers in bytes
+pub const LIMIT_HEADER_SIZE: usize = 64 * 1024;
+
+/// Max count of request headers
+pub const LIMIT_HEADERS: usize = 100;
+
+/// Max size of request line in bytes
+pub const LIMIT_REQUEST_LINE: usize = 8 * 1024;
+
+/// Max size of all responses in cache in bytes
+pub const CACHE_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// Max size of one response body in cache in bytes
+pub const CACHE_MAX_ENTRY_SIZE: usize = 1024 * 1024;
@@ -18,15 +18,14 @@ pub mod constants;
 
 /// Set spaces before capitalize letters. For change [`Http::Status`] enum items.
 pub fn space_bef_cap(src: String) -> String {
-    let chars = src.chars().into_iter();
+    let chars = src.chars();
     let mut res = "".to_string();
+    let reg = Regex::new(r"[A-Z]{1}").unwrap();
     for v in chars {
         let mut buf = [0; 2];
         v.encode_utf8(&mut buf);
-        let ch = Regex::new(r"[A-Z]{1}")
-            .unwrap()
-            .captures(&str::from_utf8(&buf).unwrap());
-        if let Some(_) = ch {
+        let ch = reg.captures(str::from_utf8(&buf).unwrap());
+        if ch.is_some() {
             if src != "OK" {
                 res.push(' ');
             } else if v == 'O' {
@@ -87,18 +86,21 @@ pub fn handle_target(client: TcpStream) -> Result<()> {
                 name: "Server".to_string(),
                 value: "echo-rs".to_string(),
             },
+            Header {
+                name: "Connection".to_string(),
+                value: "close".to_string(),
+            },
         ],
     );
 
-    client.write(res_heads.raw.as_bytes())?;
+    client.write_all(res_heads.raw())?;
 
-    if req.content_length != 0 {
-        let body = client.read_body(&req)?;
-        _log.println(LogLevel::Info, TAG, "body", str::from_utf8(&body).unwrap());
-        for i in body {
-            let chunk = format!("1{CRLF}{}{CRLF}", str::from_utf8(&[i]).unwrap());
-            client.write(chunk.as_bytes())?;
-        }
+    let body = client.read_body(&req)?;
+    _log.println(LogLevel::Info, TAG, "body", String::from_utf8_lossy(&body));
+    for i in body {
+        client.write_all(format!("1{CRLF}").as_bytes())?;
+        client.write_all(&[i])?;
+        client.write_all(CRLF.as_bytes())?;
     }
 
     client.set_zero_byte()?;
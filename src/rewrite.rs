@@ -0,0 +1,222 @@
+//! Module [`HeaderRule`].
+//! Rules adding, setting, removing and replacing headers of requests to targets and responses to clients
+use crate::http::{headers::Headers, method::Method};
+use regex::Regex;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::{Error, ErrorKind, Result},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Headers changed by rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Request sent to target
+    Request,
+    /// Response sent to client
+    Response,
+}
+
+/// Change of headers, values support variables like `${client_ip}`, see [`Variables`]
+#[derive(Clone, Debug)]
+pub enum HeaderAction {
+    /// Add header even if header with same name exists
+    Add {
+        name: String,
+        value: String,
+    },
+    /// Replace all headers with name by one with value in place of the first of them, or add new one
+    Set {
+        name: String,
+        value: String,
+    },
+    Remove {
+        name: String,
+    },
+    /// Replace matches of regex in values of headers with name, replacement supports `$1` groups
+    Replace {
+        name: String,
+        regex: Regex,
+        replacement: String,
+    },
+}
+
+impl HeaderAction {
+    pub fn add(name: &str, value: &str) -> Self {
+        HeaderAction::Add {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    pub fn set(name: &str, value: &str) -> Self {
+        HeaderAction::Set {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    pub fn remove(name: &str) -> Self {
+        HeaderAction::Remove {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn replace(name: &str, regex: &str, replacement: &str) -> Result<Self> {
+        Ok(HeaderAction::Replace {
+            name: name.to_string(),
+            regex: Regex::new(regex).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+/// Rule of changing headers applied if all its conditions are matched
+#[derive(Clone, Debug)]
+pub struct HeaderRule {
+    pub direction: Direction,
+    pub action: HeaderAction,
+    /// Name of matched route, see [`crate::router::Route::with_name`]
+    pub route: Option<String>,
+    /// Allowed methods of request, empty list allows any method
+    pub methods: Vec<Method>,
+    /// Allowed statuses of response, empty list allows any status
+    pub statuses: Vec<u16>,
+}
+
+impl HeaderRule {
+    /// Create rule changing headers of requests to targets
+    pub fn request(action: HeaderAction) -> Self {
+        HeaderRule::new(Direction::Request, action)
+    }
+
+    /// Create rule changing headers of responses to clients
+    pub fn response(action: HeaderAction) -> Self {
+        HeaderRule::new(Direction::Response, action)
+    }
+
+    pub fn new(direction: Direction, action: HeaderAction) -> Self {
+        Self {
+            direction,
+            action,
+            route: None,
+            methods: vec![],
+            statuses: vec![],
+        }
+    }
+
+    /// Apply rule only to requests matched route with name
+    pub fn with_route(mut self, name: &str) -> Self {
+        self.route = Some(name.to_string());
+        self
+    }
+
+    /// Apply rule only to listed methods of request
+    pub fn with_methods(mut self, methods: &[&str]) -> Self {
+        self.methods = methods
+            .iter()
+            .map(|m| Method::from(m.to_uppercase().as_str()))
+            .collect();
+        self
+    }
+
+    /// Apply rule only to listed statuses of response
+    pub fn with_statuses(mut self, statuses: &[u16]) -> Self {
+        self.statuses = statuses.to_vec();
+        self
+    }
+
+    /// Check conditions of rule, status is [`None`] for request
+    pub fn is_match(&self, direction: Direction, vars: &Variables, status: Option<u16>) -> bool {
+        if self.direction != direction {
+            return false;
+        }
+        if self.route.is_some() && self.route != vars.route {
+            return false;
+        }
+        if !self.methods.is_empty() && !self.methods.contains(&vars.method) {
+            return false;
+        }
+        match status {
+            Some(code) => self.statuses.is_empty() || self.statuses.contains(&code),
+            None => true,
+        }
+    }
+
+    /// Change headers by action of rule
+    pub fn apply(&self, headers: &Headers, vars: &Variables) -> Result<Headers> {
+        match &self.action {
+            HeaderAction::Add { name, value } => headers.add_header(name, &vars.interpolate(value)),
+            HeaderAction::Set { name, value } => headers.set_header(name, &vars.interpolate(value)),
+            HeaderAction::Remove { name } => headers.remove_header(name),
+            HeaderAction::Replace {
+                name,
+                regex,
+                replacement,
+            } => headers.replace_header(name, regex, &vars.interpolate(replacement)),
+        }
+    }
+
+    /// Apply all matched rules in order
+    pub fn apply_all(
+        rules: &[HeaderRule],
+        direction: Direction,
+        headers: &Headers,
+        vars: &Variables,
+        status: Option<u16>,
+    ) -> Result<Headers> {
+        let mut headers = headers.clone();
+        for rule in rules {
+            if rule.is_match(direction, vars, status) {
+                headers = rule.apply(&headers, vars)?;
+            }
+        }
+        Ok(headers)
+    }
+}
+
+/// Values of current exchange available in header values as `${name}`
+#[derive(Clone, Debug, Default)]
+pub struct Variables {
+    /// `${client_ip}` - IP address of client
+    pub client_ip: String,
+    /// `${request_id}` - value of `X-Request-Id` header of request or generated id
+    pub request_id: String,
+    /// `${upstream}` - address of chosen target
+    pub upstream: String,
+    /// `${route}` - name of matched route
+    pub route: Option<String>,
+    /// `${method}` - method of request
+    pub method: Method,
+}
+
+impl Variables {
+    /// Replace variables in value, unknown variables are left as is
+    pub fn interpolate(&self, value: &str) -> String {
+        if !value.contains("${") {
+            return value.to_string();
+        }
+        value
+            .replace("${client_ip}", &self.client_ip)
+            .replace("${request_id}", &self.request_id)
+            .replace("${upstream}", &self.upstream)
+            .replace("${route}", self.route.as_deref().unwrap_or(""))
+            .replace("${method}", self.method.as_str())
+    }
+
+    /// Generate unique id of request like `5f0c6d2e8a9b1c3d`
+    pub fn new_request_id() -> String {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0),
+        );
+        format!("{:016x}", hasher.finish())
+    }
+}
@@ -0,0 +1,220 @@
+//! Module [`Router`].
+//! Choosing group of targets by host, path and method of request
+use crate::{
+    http::{method::Method, request::Request},
+    upstream::{Balancer, UpstreamGroup},
+};
+use regex::Regex;
+use std::{
+    io::{Error, ErrorKind, Result},
+    sync::Arc,
+};
+
+/// Match of request host without port
+#[derive(Clone, Debug)]
+pub enum HostMatch {
+    /// Host is equal in any case
+    Exact(String),
+    /// Host is subdomain of domain, `*.example.com` matches `api.example.com`
+    Wildcard(String),
+}
+
+impl HostMatch {
+    /// Create exact match or wildcard match for host like `*.example.com`
+    pub fn new(host: &str) -> Self {
+        match host.strip_prefix("*.") {
+            Some(domain) => HostMatch::Wildcard(domain.to_lowercase()),
+            None => HostMatch::Exact(host.to_lowercase()),
+        }
+    }
+
+    /// Check lower case host without port
+    pub fn is_match(&self, host: &str) -> bool {
+        match self {
+            HostMatch::Exact(name) => host == name,
+            HostMatch::Wildcard(domain) => host
+                .strip_suffix(domain.as_str())
+                .map(|sub| sub.len() > 1 && sub.ends_with('.'))
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Match of request path
+#[derive(Clone, Debug)]
+pub enum PathMatch {
+    Prefix(String),
+    Regex(Regex),
+}
+
+/// Rule of routing requests to group of targets
+#[derive(Clone, Debug)]
+pub struct Route {
+    /// Name used by conditions of header rules
+    pub name: Option<String>,
+    pub host: Option<HostMatch>,
+    pub path: Option<PathMatch>,
+    /// Allowed methods, empty list allows any method
+    pub methods: Vec<Method>,
+    pub targets: UpstreamGroup,
+    /// Replacement of matched path prefix or regex, regex replacement supports `$1` groups
+    pub rewrite: Option<String>,
+}
+
+impl Route {
+    /// Create route matching every request
+    pub fn new(targets: UpstreamGroup) -> Self {
+        Self {
+            name: None,
+            host: None,
+            path: None,
+            methods: vec![],
+            targets,
+            rewrite: None,
+        }
+    }
+
+    /// Set name used by conditions of header rules
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Match host exactly or by wildcard like `*.example.com`
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.host = Some(HostMatch::new(host));
+        self
+    }
+
+    /// Match path starting with prefix
+    pub fn with_path_prefix(mut self, prefix: &str) -> Self {
+        self.path = Some(PathMatch::Prefix(prefix.to_string()));
+        self
+    }
+
+    /// Match path by regular expression
+    pub fn with_path_regex(mut self, regex: &str) -> Result<Self> {
+        let regex = Regex::new(regex).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        self.path = Some(PathMatch::Regex(regex));
+        Ok(self)
+    }
+
+    /// Match only listed methods
+    pub fn with_methods(mut self, methods: &[&str]) -> Self {
+        self.methods = methods
+            .iter()
+            .map(|m| Method::from(m.to_uppercase().as_str()))
+            .collect();
+        self
+    }
+
+    /// Remove matched path prefix before sending request to target
+    pub fn with_strip_prefix(self) -> Self {
+        self.with_rewrite("/")
+    }
+
+    /// Replace matched path prefix or regex before sending request to target
+    pub fn with_rewrite(mut self, rewrite: &str) -> Self {
+        self.rewrite = Some(rewrite.to_string());
+        self
+    }
+
+    /// Check that request matches route
+    pub fn is_match(&self, req: &Request) -> bool {
+        if !self.methods.is_empty() && !self.methods.contains(&req.method) {
+            return false;
+        }
+        if let Some(host) = &self.host {
+            if !host.is_match(&Router::get_host(req)) {
+                return false;
+            }
+        }
+        let path = &req.uri.path;
+        match &self.path {
+            None => true,
+            Some(PathMatch::Prefix(prefix)) => path.starts_with(prefix.as_str()),
+            Some(PathMatch::Regex(regex)) => regex.is_match(path),
+        }
+    }
+
+    /// Get request target after rewrite, returns [`None`] if it is not changed
+    pub fn rewrite(&self, req: &Request) -> Option<String> {
+        let rewrite = self.rewrite.as_ref()?;
+        let path = &req.uri.path;
+        let path = match self.path.as_ref()? {
+            PathMatch::Prefix(prefix) => {
+                let rest = path.strip_prefix(prefix.as_str())?;
+                let joined = format!(
+                    "{}/{}",
+                    rewrite.trim_end_matches('/'),
+                    rest.trim_start_matches('/')
+                );
+                match rest.is_empty() && !rewrite.ends_with('/') {
+                    true => rewrite.to_string(),
+                    false => joined,
+                }
+            }
+            PathMatch::Regex(regex) => regex.replace(path, rewrite.as_str()).to_string(),
+        };
+        Some(match &req.uri.query {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        })
+    }
+}
+
+/// Routes with their balancers and default balancer for requests without matched route
+pub struct Router {
+    routes: Vec<(Route, Arc<Balancer>)>,
+    default: Arc<Balancer>,
+}
+
+impl Router {
+    pub fn new(routes: &[Route], default: Arc<Balancer>) -> Self {
+        Self {
+            routes: routes
+                .iter()
+                .map(|r| (r.clone(), Arc::new(Balancer::new(r.targets.clone()))))
+                .collect(),
+            default,
+        }
+    }
+
+    /// Find first matched route and its balancer
+    pub fn route(&self, req: &Request) -> (Option<&Route>, &Arc<Balancer>) {
+        for (route, balancer) in &self.routes {
+            if route.is_match(req) {
+                return (Some(route), balancer);
+            }
+        }
+        (None, &self.default)
+    }
+
+    /// All balancers of routes including default one
+    pub fn balancers(&self) -> Vec<Arc<Balancer>> {
+        let mut res = vec![Arc::clone(&self.default)];
+        for (_, balancer) in &self.routes {
+            res.push(Arc::clone(balancer));
+        }
+        res
+    }
+
+    /// Names of routes with their balancers, default balancer goes first without name
+    pub fn groups(&self) -> Vec<(Option<String>, Arc<Balancer>)> {
+        let mut res = vec![(None, Arc::clone(&self.default))];
+        for (route, balancer) in &self.routes {
+            res.push((route.name.clone(), Arc::clone(balancer)));
+        }
+        res
+    }
+
+    /// Get lower case host of request without port
+    fn get_host(req: &Request) -> String {
+        let host = req.headers.get_header("host").unwrap_or_default();
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => host.as_str(),
+        };
+        host.to_lowercase()
+    }
+}
@@ -0,0 +1,63 @@
+//! Module [`Shutdown`].
+//! Graceful stop of listeners shared between threads
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Max time of waking connection to listener
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+struct State {
+    triggered: AtomicBool,
+    /// Addresses of listeners blocked in accept
+    listeners: Mutex<Vec<SocketAddr>>,
+}
+
+/// Handle stopping proxy server, clones share state.
+/// Listeners stop accepting, in-flight requests are finished within shutdown timeout
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    state: Arc<State>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start shutdown, it is done once
+    pub fn trigger(&self) {
+        if self.state.triggered.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // Blocked accept of every listener is released by connection to it
+        for address in self.state.listeners.lock().unwrap().iter() {
+            let _ = TcpStream::connect_timeout(address, WAKE_TIMEOUT);
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.state.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Wake listener with local address on trigger
+    pub fn register(&self, mut address: SocketAddr) {
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        self.state.listeners.lock().unwrap().push(address);
+        // Listener registered after trigger must not wait for connection
+        if self.is_triggered() {
+            let _ = TcpStream::connect_timeout(&address, WAKE_TIMEOUT);
+        }
+    }
+}
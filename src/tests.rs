@@ -1,46 +1,102 @@
+use crate::access_log::{format_clf, AccessEntry, AccessFormat, AccessLog};
+use crate::admin::Connections;
+use crate::cache::{parse_http_date, Cache, Lookup};
+use crate::forward::ForwardProxy;
+use crate::forwarded::{Cidr, ForwardedHeaders};
+use crate::health::{HealthCheck, Probe};
 use crate::http::headers::Header;
 use crate::http::request::Socket;
 use crate::http::status::Status;
-use crate::http::{headers::Headers, request::Request};
+use crate::http::{
+    body::Body,
+    connector::{Connector, Target},
+    header_map::HeaderMap,
+    headers::Headers,
+    limits::Limits,
+    method::Method,
+    parser::Head,
+    pool::{Pool, PoolConfig},
+    proxy_protocol::{ProxyHeader, ProxyVersion},
+    request::Request,
+    timeouts::Timeouts,
+    uri::{Uri, UriForm},
+    version::Version,
+};
+use crate::keep_alive::KeepAlive;
+use crate::metrics::Metrics;
 use crate::prelude::constants::TTL_DEFAULT;
-use crate::prelude::target;
+use crate::prelude::handle_target;
+use crate::rewrite::{HeaderAction, HeaderRule, Variables};
+use crate::router::{Route, Router};
+use crate::shutdown::Shutdown;
+use crate::upstream::{Balancer, HashKey, Strategy, Upstream, UpstreamGroup};
 
 #[cfg(test)]
 use super::{
     http::{Http, CRLF},
-    log::{Log, LogLevel},
+    log::{Log, LogLevel, MemorySink},
     Builder,
 };
 use std::io::{Result, Write};
 use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
     thread::{sleep, spawn},
-    time::Duration,
+    time::{Duration, UNIX_EPOCH},
 };
 
 const ECHO: [char; 4] = ['e', 'c', 'h', 'o'];
 const TAG: &str = "Test proxy server";
 
-#[test]
-pub fn test_proxy_server() -> Result<()> {
-    let _log = Log::new(&super::LOG_LEVEL);
-
-    let server = Builder::new();
+/// Start target responding with body of request in chunks, returns its address
+fn echo_target() -> Result<&'static str> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let local_addr = Box::leak(listener.local_addr()?.to_string().into_boxed_str());
     spawn(move || {
-        target(server.target).expect("Error in target");
+        for stream in listener.incoming() {
+            let _ = handle_target(stream.unwrap());
+        }
     });
+    Ok(local_addr)
+}
+
+/// Start target responding with headers of request in body, returns its address
+fn headers_target() -> Result<&'static str> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let local_addr = Box::leak(listener.local_addr()?.to_string().into_boxed_str());
     spawn(move || {
-        server.bind(None).expect("Error in proxy");
+        for stream in listener.incoming() {
+            let mut http = Http::from(stream.unwrap());
+            let heads = http.read_headers().unwrap();
+            let res = format!(
+                "HTTP/1.1 200 OK{CRLF}Server: internal{CRLF}Content-Length: {}{CRLF}{CRLF}",
+                heads.len()
+            );
+            http.write_all(res.as_bytes()).unwrap();
+            http.write_all(&heads).unwrap();
+        }
     });
-    sleep(Duration::from_secs(1));
+    Ok(local_addr)
+}
 
-    let mut http = Http::connect(server.address)?;
+#[test]
+pub fn test_proxy_server() -> Result<()> {
+    let _log = Log::new(&super::LOG_LEVEL);
+
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(echo_target()?)
+        .spawn(None)?;
+    let address = server.local_addr().to_string();
+
+    let mut http = Http::connect(&address)?;
 
     let raw_headers = Headers::new_request(
-        format!("POST / HTTP/1.1").as_str(),
+        "POST / HTTP/1.1".to_string().as_str(),
         vec![
             Header {
                 name: "Host".to_string(),
-                value: server.address.to_string(),
+                value: address.clone(),
             },
             Header {
                 name: "Content-Length".to_string(),
@@ -48,9 +104,10 @@ pub fn test_proxy_server() -> Result<()> {
             },
         ],
     )
-    .raw;
+    .raw()
+    .to_vec();
 
-    http.write(raw_headers.as_bytes())?;
+    http.write_all(&raw_headers)?;
 
     let mut t_v = vec![];
     let mut body: String = "".to_string();
@@ -61,13 +118,13 @@ pub fn test_proxy_server() -> Result<()> {
     }
     let send_body = t_v.join("");
 
-    http.write(body.as_bytes())?;
+    http.write_all(body.as_bytes())?;
 
     let buff = http.read_headers()?;
     let req = Request::new(
         Socket {
-            host: server.address.to_string(),
-            peer_addr: server.address.to_string(),
+            host: address.clone(),
+            peer_addr: address.clone(),
             ttl: TTL_DEFAULT,
             error: "".to_string(),
         },
@@ -81,7 +138,8 @@ pub fn test_proxy_server() -> Result<()> {
 
     assert_eq!(send_body, rec_body);
 
-    Ok(())
+    server.shutdown();
+    server.join()
 }
 
 #[test]
@@ -105,13 +163,13 @@ fn test_change_header_host() -> Result<()> {
             error: "".to_string(),
         },
         headers,
-    );
+    )?;
     req.change_host(super::TARGET_ADDRESS)?;
 
     assert_eq!(
-        req.headers.raw,
+        String::from_utf8_lossy(req.headers.raw()),
         format!(
-            "{}{CRLF}host: {}{CRLF}{CRLF}",
+            "{}{CRLF}Host: {}{CRLF}{CRLF}",
             status.to_full_string(),
             super::TARGET_ADDRESS
         )
@@ -119,3 +177,2172 @@ fn test_change_header_host() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_keep_alive_wait() -> Result<()> {
+    use std::{net::TcpStream, time::Instant};
+
+    let keep_alive = Arc::new(KeepAlive::new());
+    let started = Instant::now();
+    assert!(!keep_alive.wait(Duration::from_millis(20)));
+    assert!(started.elapsed() >= Duration::from_millis(20));
+
+    // Parked connection wakes waiting thread before interval passes
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let mut client = TcpStream::connect(listener.local_addr()?)?;
+    let (socket, _) = listener.accept()?;
+    let connection = Arc::new(Connections::new()).open(&socket)?;
+    let parking = Arc::clone(&keep_alive);
+    spawn(move || parking.park(Http::from(socket), connection));
+    let started = Instant::now();
+    assert!(keep_alive.wait(Duration::from_secs(5)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // Connection is taken when client sends next request
+    let (ready, closed) = keep_alive.poll(Duration::from_secs(5));
+    assert_eq!((ready.len(), closed), (0, 0));
+    client.write_all(b"GET / HTTP/1.1\r\n\r\n")?;
+    let started = Instant::now();
+    while keep_alive.poll(Duration::from_secs(5)).0.is_empty() {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        sleep(Duration::from_millis(1));
+    }
+    assert!(keep_alive.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_keep_alive() -> Result<()> {
+    let _log = Log::new(&super::LOG_LEVEL);
+
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(echo_target()?)
+        .spawn(None)?;
+    let address = server.local_addr().to_string();
+
+    let mut http = Http::connect(&address)?;
+
+    for body in ["first", "second"] {
+        let raw_headers = Headers::new_request(
+            "POST / HTTP/1.1",
+            vec![
+                Header {
+                    name: "Host".to_string(),
+                    value: address.clone(),
+                },
+                Header {
+                    name: "Content-Length".to_string(),
+                    value: body.len().to_string(),
+                },
+            ],
+        )
+        .raw()
+        .to_vec();
+        http.write_all(&raw_headers)?;
+        http.write_all(body.as_bytes())?;
+
+        let buff = http.read_headers()?;
+        let req = Request::new(
+            Socket {
+                host: address.clone(),
+                peer_addr: address.clone(),
+                ttl: TTL_DEFAULT,
+                error: "".to_string(),
+            },
+            buff,
+        )?;
+        _log.println(LogLevel::Info, TAG, "keep alive response", &req);
+        assert!(req.chunked);
+
+        let rec_body = http.read_body(&req)?;
+
+        assert_eq!(body.as_bytes(), rec_body.as_slice());
+    }
+
+    server.shutdown();
+    server.join()
+}
+
+#[test]
+fn test_pool_reuse() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?.to_string();
+    spawn(move || {
+        let mut streams = vec![];
+        for stream in listener.incoming() {
+            streams.push(stream.expect("Error in pool target"));
+        }
+    });
+
+    let pool = Arc::new(Pool::new(PoolConfig {
+        max_idle: 1,
+        max_per_host: 2,
+        idle_timeout: Duration::from_millis(200),
+    }));
+
+    let http = pool.get(&address)?;
+    assert!(!http.reused);
+    http.release();
+    assert_eq!(pool.idle(&address), 1);
+
+    let http = pool.get(&address)?;
+    assert!(http.reused);
+    assert_eq!(pool.idle(&address), 0);
+    http.release();
+
+    sleep(Duration::from_millis(300));
+    let http = pool.get(&address)?;
+    assert!(!http.reused);
+    drop(http);
+    assert_eq!(pool.idle(&address), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_binary_chunked_body() -> Result<()> {
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(echo_target()?)
+        .spawn(None)?;
+    let address = server.local_addr().to_string();
+
+    let mut http = Http::connect(&address)?;
+
+    let raw_headers = Headers::new_request(
+        "POST / HTTP/1.1",
+        vec![
+            Header {
+                name: "Host".to_string(),
+                value: address.clone(),
+            },
+            Header {
+                name: "Transfer-Encoding".to_string(),
+                value: "chunked".to_string(),
+            },
+        ],
+    )
+    .raw()
+    .to_vec();
+    http.write_all(&raw_headers)?;
+
+    let body: [u8; 6] = [0, 1, 0, 255, 13, 10];
+    http.write_all(format!("4;ext=1{CRLF}").as_bytes())?;
+    http.write_all(&body[..4])?;
+    http.write_all(format!("{CRLF}2{CRLF}").as_bytes())?;
+    http.write_all(&body[4..])?;
+    http.write_all(format!("{CRLF}0{CRLF}Checksum: 1{CRLF}{CRLF}").as_bytes())?;
+
+    let buff = http.read_headers()?;
+    let req = Request::new(
+        Socket {
+            host: address.clone(),
+            peer_addr: address.clone(),
+            ttl: TTL_DEFAULT,
+            error: "".to_string(),
+        },
+        buff,
+    )?;
+    assert!(req.chunked);
+
+    let (rec_body, trailers) = http.read_chunked()?;
+    assert_eq!(body.as_slice(), rec_body.as_slice());
+    assert!(trailers.is_empty());
+
+    server.shutdown();
+    server.join()
+}
+
+#[test]
+fn test_balancer_strategies() -> Result<()> {
+    let req = Request::create(
+        Socket {
+            host: super::PROXY_ADDRESS.to_string(),
+            peer_addr: "127.0.0.2:50000".to_string(),
+            ttl: TTL_DEFAULT,
+            error: "".to_string(),
+        },
+        Headers::new_request(
+            "GET / HTTP/1.1",
+            vec![Header {
+                name: "X-User".to_string(),
+                value: "42".to_string(),
+            }],
+        ),
+    )?;
+    let upstreams = vec![
+        Upstream::new("127.0.0.1:4001").with_weight(3),
+        Upstream::new("127.0.0.1:4002"),
+    ];
+    let select = |balancer: &Balancer| balancer.select(&req).unwrap().address().to_string();
+
+    let balancer = Balancer::new(UpstreamGroup::new(upstreams.clone()));
+    assert_eq!(select(&balancer), "127.0.0.1:4001");
+    assert_eq!(select(&balancer), "127.0.0.1:4002");
+    assert_eq!(select(&balancer), "127.0.0.1:4001");
+
+    let balancer = Balancer::new(
+        UpstreamGroup::new(upstreams.clone()).with_strategy(Strategy::WeightedRoundRobin),
+    );
+    let weighted: Vec<String> = (0..4).map(|_| select(&balancer)).collect();
+    assert_eq!(
+        weighted.iter().filter(|a| *a == "127.0.0.1:4001").count(),
+        3
+    );
+
+    let balancer = Balancer::new(
+        UpstreamGroup::new(upstreams.clone()).with_strategy(Strategy::LeastConnections),
+    );
+    let busy = balancer.select(&req).unwrap();
+    let other = balancer.select(&req).unwrap();
+    assert_ne!(busy.address(), other.address());
+    drop(other);
+    assert_ne!(select(&balancer), busy.address());
+
+    let balancer = Balancer::new(UpstreamGroup::new(upstreams).with_strategy(
+        Strategy::ConsistentHash(HashKey::Header("x-user".to_string())),
+    ));
+    let first = select(&balancer);
+    for _ in 0..10 {
+        assert_eq!(select(&balancer), first);
+    }
+
+    // Hash doesn't fall back to drained target, target with weight 0 has no points of ring
+    let balancer = Balancer::new(
+        UpstreamGroup::new(vec![
+            Upstream::new("127.0.0.1:4001"),
+            Upstream::new("127.0.0.1:4002").with_weight(0),
+        ])
+        .with_strategy(Strategy::ConsistentHash(HashKey::ClientIp)),
+    );
+    assert!(balancer.drain("127.0.0.1:4001"));
+    assert!(balancer.select(&req).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_health_check() -> Result<()> {
+    let alive = echo_target()?;
+    // Port of dropped listener is free until it is bound again
+    let dead = TcpListener::bind("127.0.0.1:0")?.local_addr()?.to_string();
+
+    let req = Request::create(
+        Socket {
+            host: super::PROXY_ADDRESS.to_string(),
+            peer_addr: super::PROXY_ADDRESS.to_string(),
+            ttl: TTL_DEFAULT,
+            error: "".to_string(),
+        },
+        Headers::new_request("GET / HTTP/1.1", vec![]),
+    )?;
+    let balancer = Balancer::new(UpstreamGroup::new(vec![
+        Upstream::new(alive),
+        Upstream::new(&dead),
+    ]));
+    let check = HealthCheck::new(Probe::Http {
+        path: "/health".to_string(),
+        status: Status::new(200),
+    })
+    .with_timeout(Duration::from_millis(500))
+    .with_thresholds(1, 1);
+    let connector = Connector::default();
+    let log = Log::new(&LogLevel::Info);
+
+    for state in balancer.upstreams() {
+        check.check(&state, &connector, &log);
+    }
+    let states = balancer.upstreams();
+    assert!(states[0].is_healthy());
+    assert!(!states[1].is_healthy());
+    for _ in 0..4 {
+        assert_eq!(balancer.select(&req).unwrap().address(), alive);
+    }
+
+    let _listener = TcpListener::bind(&dead)?;
+    let check = HealthCheck::new(Probe::Tcp).with_thresholds(1, 1);
+    check.check(&states[1], &connector, &log);
+    assert!(states[1].is_healthy());
+
+    // Thread of health checks is joined by shutdown without waiting for next round
+    let handle = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(alive)
+        .with_health_check(check.with_interval(Duration::from_secs(60)))
+        .spawn(None)?;
+    let started = std::time::Instant::now();
+    handle.shutdown();
+    handle.join()?;
+    assert!(started.elapsed() < Duration::from_secs(5));
+    Ok(())
+}
+
+#[test]
+fn test_router() -> Result<()> {
+    let request = |prefix: &str, host: &str| {
+        Request::create(
+            Socket {
+                host: super::PROXY_ADDRESS.to_string(),
+                peer_addr: super::PROXY_ADDRESS.to_string(),
+                ttl: TTL_DEFAULT,
+                error: "".to_string(),
+            },
+            Headers::new_request(
+                prefix,
+                vec![Header {
+                    name: "Host".to_string(),
+                    value: host.to_string(),
+                }],
+            ),
+        )
+        .unwrap()
+    };
+    let group = |address: &str| UpstreamGroup::new(vec![Upstream::new(address)]);
+    let router = Router::new(
+        &[
+            Route::new(group("127.0.0.1:4001"))
+                .with_host("*.example.com")
+                .with_path_prefix("/api")
+                .with_strip_prefix(),
+            Route::new(group("127.0.0.1:4002"))
+                .with_path_regex(r"^/users/(\d+)$")?
+                .with_methods(&["GET"])
+                .with_rewrite("/v2/users/$1"),
+        ],
+        Arc::new(Balancer::new(group("127.0.0.1:4000"))),
+    );
+    let target = |req: &Request| {
+        let (route, balancer) = router.route(req);
+        (
+            balancer.select(req).unwrap().address().to_string(),
+            route.and_then(|r| r.rewrite(req)),
+        )
+    };
+
+    let req = request(
+        "GET /api/items.json?page=2 HTTP/1.1",
+        "api.example.com:3000",
+    );
+    assert_eq!(
+        target(&req),
+        (
+            "127.0.0.1:4001".to_string(),
+            Some("/items.json?page=2".to_string())
+        )
+    );
+
+    let req = request("GET /api HTTP/1.1", "example.com");
+    assert_eq!(target(&req), ("127.0.0.1:4000".to_string(), None));
+
+    let mut req = request("GET /users/7 HTTP/1.1", "localhost");
+    let (address, rewrite) = target(&req);
+    assert_eq!(address, "127.0.0.1:4002");
+    req.set_target(&rewrite.unwrap())?;
+    assert!(req
+        .headers
+        .raw()
+        .starts_with(b"GET /v2/users/7 HTTP/1.1\r\n"));
+
+    let req = request("POST /users/7 HTTP/1.1", "localhost");
+    assert_eq!(target(&req), ("127.0.0.1:4000".to_string(), None));
+
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_tls_termination() -> Result<()> {
+    use crate::tls::{TlsConfig, ALPN_HTTP_1_1};
+    use rustls::{crypto::ring::default_provider, pki_types::ServerName};
+    use std::{fs, io::Read, net::TcpStream};
+
+    let dir = std::env::temp_dir().join("proxy-server-test-tls-termination");
+    fs::create_dir_all(&dir)?;
+    let mut files = vec![];
+    let mut certs = vec![];
+    for name in ["localhost", "other.test"] {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let cert_path = dir
+            .join(format!("{name}.crt"))
+            .to_string_lossy()
+            .to_string();
+        let key_path = dir
+            .join(format!("{name}.key"))
+            .to_string_lossy()
+            .to_string();
+        fs::write(&cert_path, cert.cert.pem())?;
+        fs::write(&key_path, cert.key_pair.serialize_pem())?;
+        files.push((cert_path, key_path));
+        certs.push(cert.cert.der().clone());
+    }
+
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(echo_target()?)
+        .with_tls(TlsConfig::new(&files[0].0, &files[0].1).with_sni(
+            "other.test",
+            &files[1].0,
+            &files[1].1,
+        ))
+        .spawn(None)?;
+    let address = server.local_addr().to_string();
+
+    for (name, cert) in ["localhost", "other.test"].iter().zip(certs) {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![ALPN_HTTP_1_1.to_vec()];
+        let conn = rustls::ClientConnection::new(
+            Arc::new(config),
+            ServerName::try_from(name.to_string()).unwrap(),
+        )
+        .unwrap();
+        let mut stream = rustls::StreamOwned::new(conn, TcpStream::connect(&address)?);
+
+        let raw_headers = Headers::new_request(
+            "POST / HTTP/1.1",
+            vec![
+                Header {
+                    name: "Host".to_string(),
+                    value: name.to_string(),
+                },
+                Header {
+                    name: "Content-Length".to_string(),
+                    value: ECHO.len().to_string(),
+                },
+                Header {
+                    name: "Connection".to_string(),
+                    value: "close".to_string(),
+                },
+            ],
+        )
+        .raw()
+        .to_vec();
+        stream.write_all(&raw_headers)?;
+        stream.write_all(String::from_iter(ECHO).as_bytes())?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        assert_eq!(stream.conn.alpn_protocol(), Some(ALPN_HTTP_1_1));
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&format!("1{CRLF}o{CRLF}0{CRLF}{CRLF}")));
+    }
+
+    server.shutdown();
+    server.join()
+}
+
+#[test]
+fn test_forward_proxy() -> Result<()> {
+    use std::{io::Read, net::TcpStream};
+
+    let destination = echo_target()?;
+    let port = destination.rsplit(':').next().unwrap();
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_admin_address("127.0.0.1:0")
+        .with_forward_proxy(
+            ForwardProxy::new()
+                .with_allow("127.0.0.1", None)
+                .with_deny("*", Some(1)),
+        )
+        .spawn(None)?;
+    let address = server.local_addr().to_string();
+
+    let post = |target: &str| {
+        let mut raw = Headers::new_request(
+            &format!("POST {target} HTTP/1.1"),
+            vec![
+                Header {
+                    name: "Content-Length".to_string(),
+                    value: ECHO.len().to_string(),
+                },
+                Header {
+                    name: "Connection".to_string(),
+                    value: "close".to_string(),
+                },
+            ],
+        )
+        .raw()
+        .to_vec();
+        raw.extend(String::from_iter(ECHO).into_bytes());
+        raw
+    };
+    let echo_end = format!("1{CRLF}o{CRLF}0{CRLF}{CRLF}");
+
+    // Absolute-form request is sent to its own host
+    let mut stream = TcpStream::connect(&address)?;
+    stream.write_all(&post(&format!("http://{destination}/path")))?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&echo_end));
+
+    // Request inside of tunnel is passed as is
+    let mut stream = Http::connect(&address)?;
+    stream.write_all(format!("CONNECT {destination} HTTP/1.1{CRLF}{CRLF}").as_bytes())?;
+    let heads = String::from_utf8(stream.read_headers()?).unwrap();
+    assert_eq!(
+        heads,
+        format!("HTTP/1.1 200 Connection Established{CRLF}{CRLF}")
+    );
+    stream.write_all(&post("/"))?;
+    let response = String::from_utf8(stream.read_to_close()?).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&echo_end));
+    drop(stream);
+
+    for destination in ["127.0.0.1:1".to_string(), format!("localhost:{port}")] {
+        let mut stream = TcpStream::connect(&address)?;
+        stream.write_all(format!("CONNECT {destination} HTTP/1.1{CRLF}{CRLF}").as_bytes())?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    }
+
+    // Failed destinations chosen by clients are counted under one label
+    for request in [
+        format!("CONNECT 127.0.0.1:2 HTTP/1.1{CRLF}{CRLF}"),
+        format!("GET http://127.0.0.1:3/ HTTP/1.1{CRLF}Host: 127.0.0.1:3{CRLF}{CRLF}"),
+    ] {
+        let mut stream = TcpStream::connect(&address)?;
+        stream.write_all(request.as_bytes())?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 502"));
+    }
+    let mut stream = TcpStream::connect(server.admin_addr().unwrap())?;
+    stream.write_all(format!("GET /metrics HTTP/1.1{CRLF}{CRLF}").as_bytes())?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res)?;
+    assert!(res.contains("proxy_upstream_connect_errors_total{upstream=\"forward\"} 2"));
+    assert!(!res.contains("upstream=\"127.0.0.1:"));
+
+    server.shutdown();
+    server.join()
+}
+
+#[test]
+fn test_upgrade() -> Result<()> {
+    use std::io::Read;
+
+    // Target switching protocol and echoing every message
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let target = Box::leak(listener.local_addr()?.to_string().into_boxed_str());
+    spawn(move || {
+        let mut http = Http::from(listener.accept().unwrap().0);
+        http.read_headers().unwrap();
+        let res = format!(
+            "HTTP/1.1 101 Switching Protocols{CRLF}Upgrade: websocket{CRLF}Connection: Upgrade{CRLF}{CRLF}"
+        );
+        http.write_all(res.as_bytes()).unwrap();
+        let mut b = [0; 64];
+        loop {
+            let len = http.read(&mut b).unwrap_or(0);
+            if len == 0 {
+                break;
+            }
+            http.write_all(&b[..len]).unwrap();
+        }
+    });
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .with_timeouts(Timeouts::new().with_tunnel(Duration::from_millis(500)))
+        .spawn(None)?;
+
+    let mut stream = Http::connect(&server.local_addr().to_string())?;
+    stream
+        .socket
+        .set_read_timeout(Some(Duration::from_secs(5)))?;
+    let raw_headers = Headers::new_request(
+        "GET /ws HTTP/1.1",
+        vec![
+            Header {
+                name: "Upgrade".to_string(),
+                value: "websocket".to_string(),
+            },
+            Header {
+                name: "Connection".to_string(),
+                value: "Upgrade".to_string(),
+            },
+        ],
+    )
+    .raw()
+    .to_vec();
+    stream.write_all(&raw_headers)?;
+    let heads = String::from_utf8(stream.read_headers()?).unwrap();
+    assert!(heads.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(heads.contains("Connection: Upgrade\r\n"));
+
+    // Messages are passed in both directions while connection is open
+    for message in ["ping", "pong"] {
+        stream.write_all(message.as_bytes())?;
+        let mut b = [0; 4];
+        stream.read_exact(&mut b)?;
+        assert_eq!(&b, message.as_bytes());
+    }
+
+    // Idle connection is closed by proxy
+    let mut b = [0; 1];
+    assert_eq!(stream.read(&mut b)?, 0);
+
+    server.shutdown();
+    server.join()
+}
+
+#[test]
+fn test_header_rules() -> Result<()> {
+    use std::{io::Read, net::TcpStream};
+
+    let target = headers_target()?;
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .with_route(
+            Route::new(UpstreamGroup::new(vec![Upstream::new(target)]))
+                .with_name("api")
+                .with_path_prefix("/api"),
+        )
+        .with_header_rule(HeaderRule::request(HeaderAction::set(
+            "Authorization",
+            "Bearer ${upstream}",
+        )))
+        .with_header_rule(HeaderRule::request(HeaderAction::remove("X-Internal")))
+        .with_header_rule(
+            HeaderRule::request(HeaderAction::add("X-Client", "${client_ip}"))
+                .with_methods(&["POST"]),
+        )
+        .with_header_rule(
+            HeaderRule::request(HeaderAction::add("X-Route", "${route}")).with_route("api"),
+        )
+        .with_header_rule(HeaderRule::request(HeaderAction::replace(
+            "User-Agent",
+            r"^curl/(\d+)",
+            "agent/$1",
+        )?))
+        .with_header_rule(HeaderRule::response(HeaderAction::remove("Server")))
+        .with_header_rule(
+            HeaderRule::response(HeaderAction::set("X-Request-Id", "${request_id}"))
+                .with_statuses(&[200]),
+        )
+        .with_header_rule(
+            HeaderRule::response(HeaderAction::set("X-Failed", "true")).with_statuses(&[500]),
+        )
+        .spawn(None)?;
+
+    let mut stream = TcpStream::connect(server.local_addr())?;
+    let raw_headers = Headers::new_request(
+        "GET /api/users HTTP/1.1",
+        vec![
+            Header {
+                name: "X-Internal".to_string(),
+                value: "secret".to_string(),
+            },
+            Header {
+                name: "User-Agent".to_string(),
+                value: "curl/8.5.0".to_string(),
+            },
+            Header {
+                name: "X-Request-Id".to_string(),
+                value: "abc".to_string(),
+            },
+            Header {
+                name: "Connection".to_string(),
+                value: "close".to_string(),
+            },
+        ],
+    )
+    .raw()
+    .to_vec();
+    stream.write_all(&raw_headers)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (heads, body) = response.split_once(&format!("{CRLF}{CRLF}")).unwrap();
+    assert!(heads.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(!heads.contains("Server:"));
+    assert!(heads.contains("X-Request-Id: abc\r\n"));
+    assert!(!heads.contains("X-Failed"));
+    assert!(body.contains(&format!("Authorization: Bearer {target}\r\n")));
+    assert!(body.contains("X-Route: api\r\n"));
+    assert!(body.contains("User-Agent: agent/8.5.0\r\n"));
+    assert!(!body.contains("X-Internal"));
+    assert!(!body.contains("X-Client"));
+
+    let id = Variables::new_request_id();
+    assert_eq!(id.len(), 16);
+    assert_ne!(id, Variables::new_request_id());
+
+    server.shutdown();
+    server.join()
+}
+
+#[test]
+fn test_forwarded_headers() -> Result<()> {
+    use std::{io::Read, net::TcpStream};
+
+    let target = headers_target()?;
+    let trusting = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .with_forwarded_headers(ForwardedHeaders::new().with_trusted_proxy("127.0.0.0/8")?)
+        .spawn(None)?;
+    let replacing = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .spawn(None)?;
+
+    let request = |address: SocketAddr| -> Result<String> {
+        let mut stream = TcpStream::connect(address)?;
+        let raw_headers = Headers::new_request(
+            "GET / HTTP/1.1",
+            vec![
+                Header {
+                    name: "Host".to_string(),
+                    value: "example.com".to_string(),
+                },
+                Header {
+                    name: "X-Forwarded-For".to_string(),
+                    value: "203.0.113.5".to_string(),
+                },
+                Header {
+                    name: "X-Forwarded-Proto".to_string(),
+                    value: "https".to_string(),
+                },
+                Header {
+                    name: "Forwarded".to_string(),
+                    value: "for=203.0.113.5;proto=https".to_string(),
+                },
+                Header {
+                    name: "Connection".to_string(),
+                    value: "close".to_string(),
+                },
+            ],
+        )
+        .raw()
+        .to_vec();
+        stream.write_all(&raw_headers)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    };
+
+    let body = request(trusting.local_addr())?;
+    assert!(body.contains("X-Forwarded-For: 203.0.113.5, 127.0.0.1\r\n"));
+    assert!(body.contains("X-Forwarded-Proto: https\r\n"));
+    assert!(body.contains("X-Forwarded-Host: example.com\r\n"));
+    assert!(body.contains(
+        "Forwarded: for=203.0.113.5;proto=https, for=127.0.0.1;proto=http;host=\"example.com\"\r\n"
+    ));
+
+    let body = request(replacing.local_addr())?;
+    assert!(body.contains("X-Forwarded-For: 127.0.0.1\r\n"));
+    assert!(body.contains("X-Forwarded-Proto: http\r\n"));
+    assert!(body.contains("Forwarded: for=127.0.0.1;proto=http;host=\"example.com\"\r\n"));
+
+    let cidr = Cidr::parse("10.1.0.0/16")?;
+    assert!(cidr.contains(&"10.1.200.3".parse().unwrap()));
+    assert!(!cidr.contains(&"10.2.0.1".parse().unwrap()));
+    assert!(Cidr::parse("::/0")?.contains(&"2001:db8::1".parse().unwrap()));
+    assert!(Cidr::parse("10.0.0.0/33").is_err());
+
+    for server in [trusting, replacing] {
+        server.shutdown();
+        server.join()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_proxy_protocol() -> Result<()> {
+    use std::{io::Read, net::TcpStream};
+
+    // Target responding with client address from PROXY protocol header
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let target = Box::leak(listener.local_addr()?.to_string().into_boxed_str());
+    spawn(move || {
+        let mut http = Http::from(listener.accept().unwrap().0);
+        let header = ProxyHeader::read(&mut http).unwrap().unwrap();
+        http.read_headers().unwrap();
+        let body = format!("{} {}", header.source, header.destination);
+        let res = format!(
+            "HTTP/1.1 200 OK{CRLF}Content-Length: {}{CRLF}{CRLF}{body}",
+            body.len()
+        );
+        http.write_all(res.as_bytes()).unwrap();
+    });
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .with_proxy_protocol()
+        .with_upstream_proxy_protocol(ProxyVersion::V2)
+        .spawn(None)?;
+
+    let mut stream = TcpStream::connect(server.local_addr())?;
+    stream.write_all(format!("PROXY TCP4 203.0.113.7 192.0.2.1 5555 80{CRLF}").as_bytes())?;
+    stream.write_all(format!("GET / HTTP/1.1{CRLF}Connection: close{CRLF}{CRLF}").as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("203.0.113.7:5555 192.0.2.1:80"));
+
+    let headers = [
+        ProxyHeader {
+            source: "203.0.113.7:5555".parse().unwrap(),
+            destination: "192.0.2.1:443".parse().unwrap(),
+        },
+        ProxyHeader {
+            source: "[2001:db8::1]:5555".parse().unwrap(),
+            destination: "[2001:db8::2]:443".parse().unwrap(),
+        },
+    ];
+    for header in headers {
+        for version in [ProxyVersion::V1, ProxyVersion::V2] {
+            let encoded = header.encode(version);
+            let decoded = ProxyHeader::read(&mut encoded.as_slice())?;
+            assert_eq!(decoded, Some(header));
+        }
+    }
+    let unknown = format!("PROXY UNKNOWN{CRLF}");
+    assert_eq!(ProxyHeader::read(&mut unknown.as_bytes())?, None);
+    assert!(ProxyHeader::read(&mut "GET / HTTP/1.1".as_bytes()).is_err());
+
+    server.shutdown();
+    server.join()
+}
+
+#[test]
+fn test_access_log() -> Result<()> {
+    use std::{fs, io::Read, net::TcpStream, time::UNIX_EPOCH};
+
+    let dir = std::env::temp_dir().join("proxy-server-test-access-log");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("access.log").to_string_lossy().to_string();
+
+    let target = echo_target()?;
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .with_access_log(
+            AccessLog::new(AccessFormat::Json)
+                .with_file(&path)
+                .with_max_size(1),
+        )
+        .spawn(None)?;
+    let address = server.local_addr().to_string();
+
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(&address)?;
+        let raw_headers = Headers::new_request(
+            "POST /users?id=1 HTTP/1.1",
+            vec![
+                Header {
+                    name: "Content-Length".to_string(),
+                    value: ECHO.len().to_string(),
+                },
+                Header {
+                    name: "Connection".to_string(),
+                    value: "close".to_string(),
+                },
+                Header {
+                    name: "Authorization".to_string(),
+                    value: "Basic secret".to_string(),
+                },
+            ],
+        )
+        .raw()
+        .to_vec();
+        stream.write_all(&raw_headers)?;
+        stream.write_all(String::from_iter(ECHO).as_bytes())?;
+        stream.read_to_end(&mut vec![])?;
+    }
+
+    // Every line exceeds max size, so first line is moved to rotated file
+    let files = fs::read_dir(&dir)?.count();
+    assert_eq!(files, 2);
+    let line = fs::read_to_string(&path)?;
+    assert_eq!(line.lines().count(), 1);
+    let entry: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(entry["status"], 200);
+    assert_eq!(entry["requestLine"], "POST /users?id=1 HTTP/1.1");
+    assert_eq!(entry["upstream"], target);
+    assert_eq!(entry["clientIp"], "127.0.0.1");
+    assert_eq!(entry["method"], "POST");
+    assert_eq!(entry["uri"], "/users?id=1");
+    assert_eq!(entry["version"], "HTTP/1.1");
+    assert!(entry.get("request").is_none());
+    assert!(!line.contains("secret"));
+    assert!(entry["bytesSent"].as_u64().unwrap() > 0);
+    assert!(entry["durationMs"].is_u64());
+
+    let req = Request::create(
+        Socket {
+            host: super::PROXY_ADDRESS.to_string(),
+            peer_addr: super::PROXY_ADDRESS.to_string(),
+            ttl: TTL_DEFAULT,
+            error: "".to_string(),
+        },
+        Headers::new_request(
+            "GET / HTTP/1.1",
+            vec![Header {
+                name: "User-Agent".to_string(),
+                value: "curl/8.5.0".to_string(),
+            }],
+        ),
+    )?;
+    let mut entry = AccessEntry::new(&req, "127.0.0.1");
+    entry.finish(404, 0);
+    let line = entry.format(AccessFormat::Combined)?;
+    assert!(line.starts_with("127.0.0.1 - - ["));
+    assert!(line.ends_with("] \"GET / HTTP/1.1\" 404 - \"-\" \"curl/8.5.0\""));
+    assert_eq!(
+        format_clf(UNIX_EPOCH + Duration::from_secs(971_184_936)),
+        "10/Oct/2000:13:35:36 +0000"
+    );
+
+    server.shutdown();
+    server.join()
+}
+
+#[test]
+fn test_log_sinks() -> Result<()> {
+    let sink = Arc::new(MemorySink::new());
+    let log = Log::with_sink(&LogLevel::Debug, sink.clone());
+    log.println(LogLevel::Trace, TAG, "hidden", 1);
+    log.println(LogLevel::Debug, TAG, "shown", 2);
+    log.println(LogLevel::Error, TAG, "failed", "reason");
+    assert_eq!(
+        sink.lines(),
+        vec![
+            format!("[ Debug ] <{TAG}> shown: 2"),
+            format!("[ Error ] <{TAG}> failed: \"reason\""),
+        ]
+    );
+
+    // Messages of proxy server go to sink of builder
+    let sink = Arc::new(MemorySink::new());
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target("127.0.0.1:1")
+        .with_log_sink(sink.clone())
+        .spawn(None)?;
+    let listening = format!("[ Info ] <Bind> Listening: {};", server.local_addr());
+    assert!(sink.lines()[0].starts_with(&listening));
+    server.shutdown();
+    server.join()?;
+
+    #[cfg(unix)]
+    {
+        use crate::log::{LogSink, SyslogSink};
+        use std::os::unix::net::UnixDatagram;
+
+        let path = std::env::temp_dir().join("proxy-server-test-syslog.sock");
+        let _ = std::fs::remove_file(&path);
+        let syslog = UnixDatagram::bind(&path)?;
+        let sink = SyslogSink::connect(&path.to_string_lossy(), "proxy-server")?;
+        sink.write(LogLevel::Warn, TAG, "message");
+        let mut b = [0; 128];
+        let len = syslog.recv(&mut b)?;
+        assert_eq!(
+            String::from_utf8_lossy(&b[..len]),
+            format!("<12>proxy-server: <{TAG}> message")
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_metrics() -> Result<()> {
+    use std::{io::Read, net::TcpStream};
+
+    let target = echo_target()?;
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .with_admin_address("127.0.0.1:0")
+        .with_route(
+            Route::new(UpstreamGroup::new(vec![Upstream::new(target)]))
+                .with_name("api")
+                .with_path_prefix("/api/"),
+        )
+        .spawn(None)?;
+    let admin = server.admin_addr().unwrap();
+
+    for path in ["/api/users", "/"] {
+        let mut stream = TcpStream::connect(server.local_addr())?;
+        let raw_headers = Headers::new_request(
+            &format!("POST {} HTTP/1.1", path),
+            vec![
+                Header {
+                    name: "Content-Length".to_string(),
+                    value: ECHO.len().to_string(),
+                },
+                Header {
+                    name: "Connection".to_string(),
+                    value: "close".to_string(),
+                },
+            ],
+        )
+        .raw()
+        .to_vec();
+        stream.write_all(&raw_headers)?;
+        stream.write_all(String::from_iter(ECHO).as_bytes())?;
+        stream.read_to_end(&mut vec![])?;
+    }
+
+    let mut stream = TcpStream::connect(admin)?;
+    stream.write_all(format!("GET /metrics HTTP/1.1{CRLF}Host: admin{CRLF}{CRLF}").as_bytes())?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res)?;
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(res.contains("text/plain; version=0.0.4"));
+    assert!(res.contains("proxy_requests_total{method=\"POST\",status=\"200\",route=\"api\"} 1"));
+    assert!(res.contains("proxy_requests_total{method=\"POST\",status=\"200\",route=\"\"} 1"));
+    assert!(res.contains("proxy_request_duration_seconds_count{route=\"api\"} 1"));
+    assert!(res.contains("proxy_request_duration_seconds_bucket{route=\"api\",le=\"+Inf\"} 1"));
+    let received = format!("proxy_received_bytes_total {}", ECHO.len() * 2);
+    assert!(res.contains(&received));
+    assert!(res.contains("# TYPE proxy_active_connections gauge"));
+    assert!(res.contains("proxy_thread_pool_queue_depth 0"));
+
+    let mut stream = TcpStream::connect(admin)?;
+    stream.write_all(format!("GET /unknown HTTP/1.1{CRLF}{CRLF}").as_bytes())?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res)?;
+    assert!(res.starts_with("HTTP/1.1 404"));
+    server.shutdown();
+    server.join()?;
+
+    let metrics = Metrics::new();
+    metrics.connect_error("127.0.0.1:1");
+    metrics.observe_request(&Method::Get, 502, "a\"b", Duration::from_millis(20));
+    for name in ["PROPFIND", "X1", "X2"] {
+        metrics.observe_request(&Method::from(name), 200, "", Duration::ZERO);
+    }
+    let res = metrics.render();
+    assert!(res.contains("proxy_requests_total{method=\"OTHER\",status=\"200\",route=\"\"} 3"));
+    assert!(!res.contains("PROPFIND"));
+    assert!(res.contains("proxy_upstream_connect_errors_total{upstream=\"127.0.0.1:1\"} 1"));
+    assert!(res.contains("route=\"a\\\"b\",le=\"0.01\"} 0"));
+    assert!(res.contains("route=\"a\\\"b\",le=\"0.025\"} 1"));
+    Ok(())
+}
+
+#[test]
+fn test_admin_api() -> Result<()> {
+    use std::{io::Read, net::TcpStream};
+
+    let first = echo_target()?;
+    let second = headers_target()?;
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(first)
+        .with_admin_address("127.0.0.1:0")
+        .with_admin_token("secret")
+        .spawn(None)?;
+    let admin = server.admin_addr().unwrap();
+    let send = |method: &str, path: &str, body: &str, token: &str| -> Result<String> {
+        let mut stream = TcpStream::connect(admin)?;
+        let raw = format!(
+            "{method} {path} HTTP/1.1{CRLF}Authorization: Bearer {token}{CRLF}\
+            Content-Length: {}{CRLF}{CRLF}{body}",
+            body.len()
+        );
+        stream.write_all(raw.as_bytes())?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res)?;
+        Ok(res)
+    };
+    let request = |method: &str, path: &str, body: &str| -> Result<(String, serde_json::Value)> {
+        let res = send(method, path, body, "secret")?;
+        let (head, body) = res.split_once(&format!("{CRLF}{CRLF}")).unwrap();
+        Ok((head.to_string(), serde_json::from_str(body).unwrap()))
+    };
+
+    let (head, res) = request("GET", "/targets", "")?;
+    assert!(head.starts_with("HTTP/1.1 200"));
+    assert!(head.contains("Content-Type: application/json"));
+    assert_eq!(res["groups"][0]["route"], serde_json::Value::Null);
+    assert_eq!(res["groups"][0]["targets"][0]["address"], first);
+    assert_eq!(res["groups"][0]["targets"][0]["healthy"], true);
+
+    // Changes need token, reading doesn't
+    let body = format!(r#"{{"address":"{second}"}}"#);
+    let drain = format!(r#"{{"address":"{first}"}}"#);
+    assert!(send("POST", "/targets", &body, "wrong")?.starts_with("HTTP/1.1 401"));
+    assert!(send("POST", "/targets", &body, "secre")?.starts_with("HTTP/1.1 401"));
+    assert!(send("POST", "/targets/drain", &drain, "")?.starts_with("HTTP/1.1 401"));
+    assert!(send("PUT", "/log-level", r#"{"level":"debug"}"#, "")?.starts_with("HTTP/1.1 401"));
+    assert!(send("GET", "/connections", "", "")?.starts_with("HTTP/1.1 200"));
+
+    let (head, res) = request("POST", "/targets", &body)?;
+    assert!(head.starts_with("HTTP/1.1 201"));
+    assert_eq!(res["weight"], 1);
+    let (head, _) = request("POST", "/targets/drain", &drain)?;
+    assert!(head.starts_with("HTTP/1.1 200"));
+    let (head, _) = request("POST", "/targets/drain", r#"{"address":"127.0.0.1:1"}"#)?;
+    assert!(head.starts_with("HTTP/1.1 404"));
+    let (head, _) = request("POST", "/targets", "address")?;
+    assert!(head.starts_with("HTTP/1.1 400"));
+    let (head, res) = request("POST", "/targets", r#"{"address":"127.0.0.1:99999"}"#)?;
+    assert!(head.starts_with("HTTP/1.1 400"));
+    assert!(res["error"].as_str().unwrap().contains("port"));
+    let (head, _) = request("POST", "/targets", r#"{"address":"ftp://127.0.0.1"}"#)?;
+    assert!(head.starts_with("HTTP/1.1 400"));
+
+    let (_, res) = request("GET", "/targets", "")?;
+    let targets = &res["groups"][0]["targets"];
+    assert_eq!(targets[0]["draining"], true);
+    assert_eq!(targets[1]["address"], second);
+
+    // Drained target doesn't get new requests
+    let mut http = Http::connect(&server.local_addr().to_string())?;
+    for _ in 0..2 {
+        http.write_all(format!("GET / HTTP/1.1{CRLF}Host: proxy{CRLF}{CRLF}").as_bytes())?;
+        let head = String::from_utf8(http.read_headers()?).unwrap();
+        assert!(head.contains("Server: internal"));
+        let len = Headers::from_string(head.clone()).content_length().unwrap();
+        http.read_exact(&mut vec![0; len])?;
+    }
+    let (_, res) = request("GET", "/connections", "")?;
+    assert_eq!(res["active"], 1);
+    assert_eq!(res["connections"][0]["requests"], 2);
+    assert_eq!(
+        res["connections"][0]["client"],
+        http.local_addr()?.to_string()
+    );
+    drop(http);
+
+    let (head, res) = request("PUT", "/log-level", r#"{"level":"debug"}"#)?;
+    assert!(head.starts_with("HTTP/1.1 200"));
+    assert_eq!(res["level"], "Debug");
+    let (_, res) = request("GET", "/log-level", "")?;
+    assert_eq!(res["level"], "Debug");
+    let (head, _) = request("PUT", "/log-level", r#"{"level":"loud"}"#)?;
+    assert!(head.starts_with("HTTP/1.1 400"));
+    let (head, _) = request("DELETE", "/connections", "")?;
+    assert!(head.starts_with("HTTP/1.1 405"));
+
+    // Large body is rejected before it is read
+    let mut stream = TcpStream::connect(admin)?;
+    let raw = format!("PUT /log-level HTTP/1.1{CRLF}Content-Length: 1000000{CRLF}{CRLF}");
+    stream.write_all(raw.as_bytes())?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res)?;
+    assert!(res.starts_with("HTTP/1.1 413"));
+
+    server.shutdown();
+    server.join()
+}
+
+#[test]
+fn test_shutdown() -> Result<()> {
+    use std::{io::Read, net::TcpStream, sync::mpsc, time::Instant};
+
+    // Target answers after pause, so request is in progress while shutdown is triggered
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let target = Box::leak(listener.local_addr()?.to_string().into_boxed_str());
+    let (received, requests) = mpsc::channel();
+    spawn(move || {
+        for stream in listener.incoming() {
+            let mut http = Http::from(stream.unwrap());
+            let received = received.clone();
+            spawn(move || {
+                while !http.read_headers().unwrap().is_empty() {
+                    received.send(()).unwrap();
+                    sleep(Duration::from_millis(500));
+                    let res = format!("HTTP/1.1 200 OK{CRLF}Content-Length: 4{CRLF}{CRLF}done");
+                    http.write_all(res.as_bytes()).unwrap();
+                }
+            });
+        }
+    });
+
+    let shutdown = Shutdown::new();
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .with_shutdown(shutdown.clone())
+        .with_shutdown_timeout(Duration::from_secs(5))
+        .spawn(None)?;
+    let address = server.local_addr();
+
+    let request = format!("GET / HTTP/1.1{CRLF}Host: proxy{CRLF}{CRLF}");
+    let mut idle = Http::connect(&address.to_string())?;
+    idle.write_all(request.as_bytes())?;
+    idle.read_headers()?;
+    idle.read_exact(&mut [0; 4])?;
+    requests.recv().unwrap();
+
+    let mut active = TcpStream::connect(address)?;
+    active.write_all(request.as_bytes())?;
+    requests.recv().unwrap();
+    let started = Instant::now();
+    shutdown.trigger();
+
+    // Request in progress is finished and connection is closed after it
+    let mut res = String::new();
+    active.read_to_string(&mut res)?;
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(res.contains("Connection: close"));
+    assert!(res.ends_with("done"));
+
+    server.join()?;
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(idle.read(&mut [0; 1]).unwrap_or(0), 0);
+    assert!(TcpStream::connect(address).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_server_handle() -> Result<()> {
+    use std::{io::Read, net::TcpStream};
+
+    let handle = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(headers_target()?)
+        .with_admin_address("127.0.0.1:0")
+        .spawn(None)?;
+    let address = handle.local_addr();
+    assert_ne!(address.port(), 0);
+
+    // Listener is bound when spawn returns, so there is no need to wait for it
+    let mut stream = TcpStream::connect(address)?;
+    stream.write_all(format!("GET / HTTP/1.1{CRLF}Connection: close{CRLF}{CRLF}").as_bytes())?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res)?;
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(res.contains("Server: internal"));
+
+    let admin = handle.admin_addr().unwrap();
+    assert_ne!(admin.port(), 0);
+    let mut stream = TcpStream::connect(admin)?;
+    stream.write_all(format!("GET /metrics HTTP/1.1{CRLF}{CRLF}").as_bytes())?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res)?;
+    assert!(res.contains("proxy_requests_total{method=\"GET\",status=\"200\",route=\"\"} 1"));
+
+    handle.shutdown();
+    handle.join()?;
+    assert!(TcpStream::connect(address).is_err());
+    assert!(TcpStream::connect(admin).is_err());
+
+    let busy = TcpListener::bind("127.0.0.1:0")?;
+    let res = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_admin_address(Box::leak(busy.local_addr()?.to_string().into_boxed_str()))
+        .spawn(None);
+    assert!(res.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_timeouts() -> Result<()> {
+    use std::{io::Read, net::TcpStream, time::Instant};
+
+    // Target reads requests and never answers
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let target_address = Box::leak(listener.local_addr()?.to_string().into_boxed_str());
+    spawn(move || {
+        let mut streams = vec![];
+        for stream in listener.incoming() {
+            let mut http = Http::from(stream.unwrap());
+            http.read_headers().unwrap();
+            streams.push(http);
+        }
+    });
+    let handle = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target_address)
+        .with_timeouts(
+            Timeouts::new()
+                .with_header(Duration::from_millis(300))
+                .with_body(Duration::from_millis(300))
+                .with_response(Duration::from_millis(300))
+                .with_idle(Duration::from_millis(300)),
+        )
+        .spawn(None)?;
+    let address = handle.local_addr();
+    let read_all = |stream: &mut TcpStream| -> Result<String> {
+        let mut res = String::new();
+        stream.read_to_string(&mut res)?;
+        Ok(res)
+    };
+
+    // Client sending headers slowly is answered with 408
+    let mut stream = TcpStream::connect(address)?;
+    let started = Instant::now();
+    // Every byte comes before idle timeout, but headers are not completed in header timeout
+    for b in "GET".bytes() {
+        stream.write_all(&[b])?;
+        sleep(Duration::from_millis(100));
+    }
+    assert!(read_all(&mut stream)?.starts_with("HTTP/1.1 408"));
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    // Connection without requests is closed silently
+    let mut stream = TcpStream::connect(address)?;
+    assert_eq!(read_all(&mut stream)?, "");
+
+    // Incomplete request body
+    let mut stream = TcpStream::connect(address)?;
+    stream
+        .write_all(format!("POST / HTTP/1.1{CRLF}Content-Length: 10{CRLF}{CRLF}ab").as_bytes())?;
+    assert!(read_all(&mut stream)?.starts_with("HTTP/1.1 408"));
+
+    // Target doesn't answer
+    let mut stream = TcpStream::connect(address)?;
+    stream.write_all(format!("GET / HTTP/1.1{CRLF}Host: proxy{CRLF}{CRLF}").as_bytes())?;
+    assert!(read_all(&mut stream)?.starts_with("HTTP/1.1 504"));
+
+    handle.shutdown();
+    handle.join()
+}
+
+#[test]
+fn test_limits() -> Result<()> {
+    use std::{io::Read, net::TcpStream};
+
+    // Target fails on the last request with body interrupted by proxy
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let target_address = Box::leak(listener.local_addr()?.to_string().into_boxed_str());
+    spawn(move || -> Result<()> {
+        for stream in listener.incoming() {
+            handle_target(stream?)?;
+        }
+        Ok(())
+    });
+    let handle = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target_address)
+        .with_limits(
+            Limits::new()
+                .with_header_size(1024)
+                .with_headers(10)
+                .with_request_line(64)
+                .with_body_size(8),
+        )
+        .spawn(None)?;
+    let address = handle.local_addr();
+    let send = |req: String| -> Result<String> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.write_all(req.as_bytes())?;
+        let mut res = [0; 12];
+        stream.read_exact(&mut res)?;
+        Ok(String::from_utf8_lossy(&res).to_string())
+    };
+    let request = |headers: &str| format!("POST / HTTP/1.1{CRLF}Host: proxy{CRLF}{headers}{CRLF}");
+
+    assert_eq!(send(request(""))?, "HTTP/1.1 200");
+    assert_eq!(
+        send(request(&format!("X-Big: {}{CRLF}", "a".repeat(2048))))?,
+        "HTTP/1.1 431"
+    );
+    assert_eq!(
+        send(request(&format!("X-Header: a{CRLF}").repeat(11)))?,
+        "HTTP/1.1 431"
+    );
+    let uri = "a".repeat(100);
+    assert_eq!(
+        send(format!("GET /{uri} HTTP/1.1{CRLF}Host: proxy{CRLF}{CRLF}"))?,
+        "HTTP/1.1 414"
+    );
+
+    // Ambiguous framing is rejected before reaching target
+    for headers in [
+        format!("Content-Length: 1{CRLF}Content-Length: 2{CRLF}"),
+        format!("Content-Length: 1{CRLF}Transfer-Encoding: chunked{CRLF}"),
+        format!("Transfer-Encoding: chunked, gzip{CRLF}"),
+        format!("Content-Length : 1{CRLF}"),
+        format!("Content-Length: +1{CRLF}"),
+        format!("X-Folded: a{CRLF} b{CRLF}"),
+        format!("X-Bare-Cr: a\rb{CRLF}"),
+        format!("X-Nul: a\0b{CRLF}"),
+    ] {
+        assert_eq!(send(request(&headers))?, "HTTP/1.1 400", "{headers}");
+    }
+    for line in [
+        "GET  / HTTP/1.1",
+        "GET / HTTP/1.1 x",
+        "GET / HTTP/9",
+        "G\0T / HTTP/1.1",
+        "HTTP/1.1 200 OK",
+    ] {
+        assert_eq!(
+            send(format!("{line}{CRLF}Host: proxy{CRLF}{CRLF}"))?,
+            "HTTP/1.1 400",
+            "{line:?}"
+        );
+    }
+
+    // Body over limit by length or by chunks
+    assert_eq!(
+        send(request(&format!("Content-Length: 100{CRLF}")))?,
+        "HTTP/1.1 413"
+    );
+    assert_eq!(
+        send(format!(
+            "{}5{CRLF}abcde{CRLF}0{CRLF}{CRLF}",
+            request(&format!("Transfer-Encoding: chunked{CRLF}"))
+        ))?,
+        "HTTP/1.1 200"
+    );
+    assert_eq!(
+        send(format!(
+            "{}5{CRLF}abcde{CRLF}5{CRLF}abcde{CRLF}0{CRLF}{CRLF}",
+            request(&format!("Transfer-Encoding: chunked{CRLF}"))
+        ))?,
+        "HTTP/1.1 413"
+    );
+
+    // Chunked is detected only in Transfer-Encoding as the last coding
+    assert!(!Headers::from_string(format!("X-Transfer-Encoding: chunked{CRLF}")).is_chunked());
+    assert!(!Headers::from_string(format!("Transfer-Encoding: chunked, gzip{CRLF}")).is_chunked());
+    assert!(Headers::from_string(format!("Transfer-Encoding: gzip, chunked{CRLF}")).is_chunked());
+
+    handle.shutdown();
+    handle.join()
+}
+
+#[test]
+fn test_parser() -> Result<()> {
+    let raw = format!(
+        "GET /a%20b/./~c.txt?x=1&y=%2F HTTP/1.1{CRLF}Host: proxy{CRLF}\
+        Accept:  text/html \t{CRLF}ACCEPT: text/plain{CRLF}Connection: Keep-Alive, Upgrade{CRLF}\
+        Upgrade: websocket{CRLF}{CRLF}body"
+    );
+    let head = Head::parse(raw.as_bytes());
+    let line = head.request_line().unwrap();
+    assert_eq!(line.method, "GET");
+    assert_eq!(line.path(), "/a%20b/./~c.txt");
+    assert_eq!(line.query(), "?x=1&y=%2F");
+    assert_eq!(line.version, "HTTP/1.1");
+    assert_eq!(head.len, raw.len() - "body".len());
+    assert!(head.complete);
+    assert_eq!(head.fields.len(), 5);
+    assert_eq!(head.fields.get("accept"), Some(&b"text/html"[..]));
+    assert_eq!(head.fields.get_all("Accept").count(), 2);
+    assert!(head.keep_alive());
+    assert!(head.upgrade());
+    assert!(Head::parse_strict(raw.as_bytes()).is_ok());
+
+    // Values are kept as bytes, names are ordered as received
+    let raw = b"HTTP/1.0 404 Not Found\r\nX-Bin: a\xffb\r\nContent-Length: 12\r\n\r\n";
+    let head = Head::parse(raw);
+    let status = head.status_line().unwrap();
+    assert_eq!(
+        (status.version, status.code, status.reason),
+        ("HTTP/1.0", 404, "Not Found")
+    );
+    assert_eq!(head.fields.get("x-bin"), Some(&b"a\xffb"[..]));
+    let names: Vec<&str> = head.fields.iter().map(|f| f.name).collect();
+    assert_eq!(names, ["X-Bin", "Content-Length"]);
+    assert_eq!(head.fields.content_length(), Some(12));
+    assert!(!head.keep_alive());
+
+    // Lengths over 4 GiB are not truncated
+    let raw = "HTTP/1.1 200 OK\r\nContent-Length: 5000000000\r\n\r\n";
+    let headers = Headers::from_string(raw.to_string());
+    assert_eq!(headers.content_length(), Some(5_000_000_000));
+    let raw = "POST / HTTP/1.1\r\nContent-Length: 5000000000\r\n\r\n";
+    let socket = Socket {
+        host: "".to_string(),
+        peer_addr: "".to_string(),
+        ttl: TTL_DEFAULT,
+        error: "".to_string(),
+    };
+    let req = Request::create(socket, Headers::from(raw.to_string()))?;
+    assert!(matches!(Body::request(&req), Body::Length(5_000_000_000)));
+
+    assert_eq!(
+        Head::parse(b"CONNECT example.com:443 HTTP/1.1\r\n\r\n")
+            .request_line()
+            .unwrap()
+            .path(),
+        "/"
+    );
+    assert_eq!(
+        Headers::from_string(format!(
+            "GET http://example.com/p.html?q HTTP/1.1{CRLF}{CRLF}"
+        ))
+        .target(),
+        Some("http://example.com/p.html?q")
+    );
+
+    // Trailers have no start line
+    let head = Head::parse(b"X-Checksum: 1\r\n\r\n");
+    assert_eq!(head.fields.get("x-checksum"), Some(&b"1"[..]));
+
+    assert!(Head::parse_strict(b"GET / HTTP/1.1\r\nHost: a\r\n").is_err());
+    assert!(Head::parse_strict(b"GET /\r\n\r\n").is_err());
+    assert!(Head::parse_strict(b"GET / HTTP/1.1\r\nBad header\r\n\r\n").is_err());
+    assert!(Head::parse_strict(b"GET / HTTP/1.1\r\nA: b\rc\r\n\r\n").is_err());
+    assert!(Head::parse_strict(b"GET / HTTP/1.1\r\nA: b\0c\r\n\r\n").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_typed_request() -> Result<()> {
+    assert_eq!(Method::from("PATCH"), Method::Patch);
+    assert_eq!(Method::from("get"), Method::Extension("get".to_string()));
+    assert_eq!(Method::from("PROPFIND").to_string(), "PROPFIND");
+    assert!(Method::Put.is_idempotent() && !Method::Put.is_safe());
+
+    assert_eq!("HTTP/1.0".parse::<Version>()?, Version::Http10);
+    assert_eq!("HTTP/1.2".parse::<Version>()?, Version::Http11);
+    assert!("HTTP/2.0".parse::<Version>().is_err());
+    assert!("OPTIONS".parse::<Version>().is_err());
+
+    let uri = Uri::parse("/a%20b/c?x=1&y=a+b%26c&flag&x=2")?;
+    assert_eq!(uri.form, UriForm::Origin);
+    assert_eq!(uri.path, "/a%20b/c");
+    assert_eq!(uri.decoded_path(), "/a b/c");
+    assert_eq!(
+        uri.query_pairs(),
+        [
+            ("x".to_string(), "1".to_string()),
+            ("y".to_string(), "a b&c".to_string()),
+            ("flag".to_string(), "".to_string()),
+            ("x".to_string(), "2".to_string()),
+        ]
+    );
+    assert_eq!(uri.query_param("x").as_deref(), Some("1"));
+    assert_eq!(uri.to_string(), "/a%20b/c?x=1&y=a+b%26c&flag&x=2");
+
+    let uri = Uri::parse("HTTP://example.com:8080?q=%zz")?;
+    assert_eq!(uri.form, UriForm::Absolute);
+    assert_eq!(uri.scheme.as_deref(), Some("http"));
+    assert_eq!(uri.authority.as_deref(), Some("example.com:8080"));
+    assert_eq!(uri.origin(), "/?q=%zz");
+    assert_eq!(uri.query_param("q").as_deref(), Some("%zz"));
+
+    let uri = Uri::parse("example.com:443")?;
+    assert_eq!(uri.form, UriForm::Authority);
+    assert_eq!(uri.to_string(), "example.com:443");
+    assert_eq!(Uri::parse("*")?.form, UriForm::Asterisk);
+    assert!(Uri::parse("example.com").is_err());
+    assert!(Uri::parse("").is_err());
+
+    let socket = || Socket {
+        host: "proxy".to_string(),
+        peer_addr: "127.0.0.1:1".to_string(),
+        ttl: TTL_DEFAULT,
+        error: "".to_string(),
+    };
+    let req = Request::new(
+        socket(),
+        format!("DELETE /items/~1.json?force=true HTTP/1.0{CRLF}{CRLF}").into_bytes(),
+    )?;
+    assert_eq!(req.method, Method::Delete);
+    assert_eq!(req.version, Version::Http10);
+    assert_eq!(req.uri.path, "/items/~1.json");
+    assert_eq!(req.uri.query_param("force").as_deref(), Some("true"));
+    let json = serde_json::to_value(&req)?;
+    assert_eq!(json["method"], "DELETE");
+    assert_eq!(json["version"], "HTTP/1.0");
+    assert_eq!(json["uri"], "/items/~1.json?force=true");
+
+    let res = Request::new(
+        socket(),
+        format!("HTTP/1.0 200 OK{CRLF}{CRLF}").into_bytes(),
+    )?;
+    assert_eq!(res.version, Version::Http10);
+    assert_eq!(res.headers.version(), Some(Version::Http10));
+    assert_eq!(res.headers.method(), None);
+    Ok(())
+}
+
+#[test]
+fn test_header_map() -> Result<()> {
+    let mut map = HeaderMap::parse(
+        format!("GET / HTTP/1.1{CRLF}Accept: a{CRLF}X-Tag: 1{CRLF}ACCEPT: b{CRLF}{CRLF}")
+            .as_bytes(),
+    );
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.get("accept"), Some("a"));
+    assert_eq!(map.get_all("Accept").collect::<Vec<_>>(), ["a", "b"]);
+
+    // Insert keeps case and place of the first header and drops the others
+    map.insert("accept", "c")?;
+    assert_eq!(map.get_all("accept").collect::<Vec<_>>(), ["c"]);
+    map.append("x-tag", "2")?;
+    map.insert("Via", "proxy")?;
+    assert_eq!(
+        map.to_string(),
+        format!("Accept: c{CRLF}X-Tag: 1{CRLF}x-tag: 2{CRLF}Via: proxy{CRLF}{CRLF}")
+    );
+    assert_eq!(map.remove("X-TAG").as_deref(), Some("1"));
+    assert!(!map.contains("x-tag"));
+    assert_eq!(map.remove("x-tag"), None);
+
+    // Values can't inject other lines
+    assert!(map.insert("X-Bad", &format!("a{CRLF}Host: evil")).is_err());
+    assert!(map.append("Bad Name", "a").is_err());
+    assert_eq!(
+        map.to_bytes(),
+        format!("Accept: c{CRLF}Via: proxy{CRLF}{CRLF}").into_bytes()
+    );
+
+    // Raw text and list of headers are changed together, status line is kept as is
+    let headers = Headers::from_string(format!(
+        "HTTP/1.0 200 Fine{CRLF}Content-Type: text/plain{CRLF}{CRLF}"
+    ))
+    .set_header("content-type", "text/html")?
+    .add_header("Set-Cookie", "a=1")?
+    .add_header("Set-Cookie", "b=2")?;
+    assert_eq!(
+        String::from_utf8_lossy(headers.raw()),
+        format!(
+            "HTTP/1.0 200 Fine{CRLF}Content-Type: text/html{CRLF}\
+            Set-Cookie: a=1{CRLF}Set-Cookie: b=2{CRLF}{CRLF}"
+        )
+    );
+    assert_eq!(headers.header_map(), HeaderMap::parse(headers.raw()));
+    assert_eq!(headers.header_map().get_all("set-cookie").count(), 2);
+    assert_eq!(headers.remove_header("SET-COOKIE")?.list().len(), 1);
+
+    // Serialized list is derived from raw text, so they can't differ
+    let json = serde_json::to_value(&headers).unwrap();
+    assert_eq!(json["list"][1]["value"], "a=1");
+    let raw = String::from(headers.clone());
+    let parsed: Headers = serde_json::from_value(serde_json::json!({ "raw": raw })).unwrap();
+    assert_eq!(parsed.header_map(), headers.header_map());
+
+    // Values which are not UTF-8 are kept as bytes when other headers are changed
+    let raw = b"GET / HTTP/1.1\r\nX-Bin: a\xffb\r\nHost: a\r\n\r\n".to_vec();
+    let socket = Socket {
+        host: "a".to_string(),
+        peer_addr: "127.0.0.1:1".to_string(),
+        ttl: TTL_DEFAULT,
+        error: "".to_string(),
+    };
+    let mut req = Request::new(socket, raw)?;
+    req.change_host("b")?;
+    req.set_target("/path")?;
+    assert_eq!(
+        req.headers.raw(),
+        b"GET /path HTTP/1.1\r\nX-Bin: a\xffb\r\nHost: b\r\n\r\n"
+    );
+    assert_eq!(
+        req.headers.get_header("x-bin"),
+        Some("a\u{fffd}b".to_string())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_cache() -> Result<()> {
+    use std::{
+        io::Read,
+        net::TcpStream,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    // Target answers by path and counts requests, which reach it
+    let hits = Arc::new(AtomicUsize::new(0));
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let target_address = Box::leak(listener.local_addr()?.to_string().into_boxed_str());
+    let counter = Arc::clone(&hits);
+    spawn(move || {
+        for stream in listener.incoming() {
+            let counter = Arc::clone(&counter);
+            spawn(move || {
+                let mut http = Http::from(stream.unwrap());
+                loop {
+                    let heads = match http.read_headers() {
+                        Ok(heads) if !heads.is_empty() => heads,
+                        _ => return,
+                    };
+                    let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                    let head = Head::parse(&heads);
+                    let path = head.request_line().unwrap().target.to_string();
+                    let fields = HeaderMap::parse(&heads);
+                    let lang = fields.get("Accept-Language").unwrap_or("");
+                    let (status, headers, body) = match path.as_str() {
+                        "/etag" if fields.get("If-None-Match") == Some("\"v1\"") => {
+                            ("304 Not Modified", "ETag: \"v1\"", "".to_string())
+                        }
+                        "/etag" => (
+                            "200 OK",
+                            "Cache-Control: no-cache\r\nETag: \"v1\"",
+                            format!("etag-{n}"),
+                        ),
+                        "/vary" => (
+                            "200 OK",
+                            "Cache-Control: max-age=60\r\nVary: Accept-Language",
+                            format!("{lang}-{n}"),
+                        ),
+                        "/private" => (
+                            "200 OK",
+                            "Cache-Control: private, max-age=60",
+                            format!("private-{n}"),
+                        ),
+                        "/chunked" => (
+                            "200 OK",
+                            "Cache-Control: max-age=60",
+                            format!("chunked-{n}-").repeat(4),
+                        ),
+                        _ => ("200 OK", "Cache-Control: max-age=60", format!("fresh-{n}")),
+                    };
+                    let res = match path.as_str() {
+                        "/chunked" => format!(
+                            "HTTP/1.1 {status}{CRLF}{headers}{CRLF}Transfer-Encoding: chunked{CRLF}{CRLF}{}0{CRLF}{CRLF}",
+                            body.as_bytes()
+                                .chunks(body.len() / 4)
+                                .map(|c| format!("{:x}{CRLF}{}{CRLF}", c.len(), str::from_utf8(c).unwrap()))
+                                .collect::<String>()
+                        ),
+                        _ => format!(
+                            "HTTP/1.1 {status}{CRLF}{headers}{CRLF}Content-Length: {}{CRLF}{CRLF}{body}",
+                            body.len()
+                        ),
+                    };
+                    http.write_all(res.as_bytes()).unwrap();
+                }
+            });
+        }
+    });
+    let handle = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target_address)
+        .with_cache(Cache::new().with_max_entry_size(32))
+        .with_header_rule(HeaderRule::response(HeaderAction::set(
+            "X-Request-Id",
+            "${request_id}",
+        )))
+        .spawn(None)?;
+    let address = handle.local_addr();
+    let get = |path: &str, headers: &str| -> Result<(HeaderMap, String)> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.write_all(
+            format!(
+                "GET {path} HTTP/1.1{CRLF}Host: proxy{CRLF}{headers}Connection: close{CRLF}{CRLF}"
+            )
+            .as_bytes(),
+        )?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res)?;
+        let (head, body) = res.split_once("\r\n\r\n").unwrap();
+        Ok((HeaderMap::parse(head.as_bytes()), body.to_string()))
+    };
+
+    // Fresh response is stored on first request and served from cache then
+    let (res, body) = get("/fresh", "")?;
+    assert_eq!(res.get("X-Cache"), Some("MISS"));
+    assert_eq!(body, "fresh-1");
+    let (res, body) = get("/fresh", "X-Request-Id: second\r\n")?;
+    assert_eq!(res.get("X-Cache"), Some("HIT"));
+    assert_eq!(res.get("Age"), Some("0"));
+    assert_eq!(body, "fresh-1");
+    // Response rules are applied to every response from cache, not stored with it
+    assert_eq!(res.get("X-Request-Id"), Some("second"));
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+    // Request can bypass cache or ask to revalidate response
+    let (res, body) = get("/fresh", "Cache-Control: no-store\r\n")?;
+    assert_eq!(res.get("X-Cache"), Some("BYPASS"));
+    assert_eq!(body, "fresh-2");
+    let (res, body) = get("/fresh", "Authorization: Basic dTpw\r\n")?;
+    assert_eq!(res.get("X-Cache"), Some("BYPASS"));
+    assert_eq!(body, "fresh-3");
+    let (res, body) = get("/fresh", "Cache-Control: no-cache\r\n")?;
+    assert_eq!(res.get("X-Cache"), Some("MISS"));
+    assert_eq!(body, "fresh-4");
+
+    // Response with `no-cache` is revalidated by `ETag` before every use
+    let (res, body) = get("/etag", "")?;
+    assert_eq!(res.get("X-Cache"), Some("MISS"));
+    assert_eq!(body, "etag-5");
+    let (res, body) = get("/etag", "X-Request-Id: third\r\n")?;
+    assert_eq!(res.get("X-Cache"), Some("REVALIDATED"));
+    assert_eq!(res.get("X-Request-Id"), Some("third"));
+    assert_eq!(res.get("ETag"), Some("\"v1\""));
+    assert_eq!(res.get("Content-Length"), Some("6"));
+    assert_eq!(body, "etag-5");
+    assert_eq!(hits.load(Ordering::SeqCst), 6);
+
+    // Responses differ by headers of `Vary`
+    assert_eq!(get("/vary", "Accept-Language: en\r\n")?.1, "en-7");
+    assert_eq!(get("/vary", "Accept-Language: de\r\n")?.1, "de-8");
+    let (res, body) = get("/vary", "Accept-Language: en\r\n")?;
+    assert_eq!(res.get("X-Cache"), Some("HIT"));
+    assert_eq!(body, "en-7");
+
+    // Private response is not stored
+    assert_eq!(get("/private", "")?.1, "private-9");
+    assert_eq!(get("/private", "")?.1, "private-10");
+
+    // Chunked body longer than max size of entry is streamed to client without storing
+    for n in [11, 12] {
+        let (res, body) = get("/chunked", "")?;
+        assert_eq!(res.get("X-Cache"), Some("MISS"));
+        assert_eq!(res.get("Transfer-Encoding"), Some("chunked"));
+        let mut decoded = String::new();
+        let mut rest = body.as_str();
+        while let Some((size, data)) = rest.split_once(CRLF) {
+            let size = usize::from_str_radix(size, 16).unwrap();
+            decoded.push_str(&data[..size]);
+            rest = &data[size + CRLF.len()..];
+        }
+        assert_eq!(decoded, format!("chunked-{n}-").repeat(4));
+        assert!(body.ends_with(&format!("0{CRLF}{CRLF}")));
+    }
+
+    // Unsafe request removes stored response
+    let mut stream = TcpStream::connect(address)?;
+    stream.write_all(
+        format!("POST /fresh HTTP/1.1{CRLF}Host: proxy{CRLF}Content-Length: 0{CRLF}Connection: close{CRLF}{CRLF}")
+            .as_bytes(),
+    )?;
+    stream.read_to_end(&mut vec![])?;
+    let (res, body) = get("/fresh", "")?;
+    assert_eq!(res.get("X-Cache"), Some("MISS"));
+    assert_eq!(body, "fresh-14");
+
+    // Invalid target is rejected instead of being read as `/` from cache
+    let mut stream = TcpStream::connect(address)?;
+    stream.write_all(format!("GET bogus HTTP/1.1{CRLF}Host: proxy{CRLF}{CRLF}").as_bytes())?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res)?;
+    assert!(res.starts_with("HTTP/1.1 400"));
+    handle.shutdown();
+    handle.join()?;
+
+    // Least recently used response is evicted, bodies are kept in files of directory
+    let dir = std::env::temp_dir().join("proxy-server-test-cache");
+    let dir = dir.to_string_lossy().to_string();
+    let request = |path: &str| {
+        Request::create(
+            Socket {
+                host: super::PROXY_ADDRESS.to_string(),
+                peer_addr: super::PROXY_ADDRESS.to_string(),
+                ttl: TTL_DEFAULT,
+                error: "".to_string(),
+            },
+            Headers::from_string(format!("GET {path} HTTP/1.1{CRLF}Host: proxy{CRLF}{CRLF}")),
+        )
+        .unwrap()
+    };
+    let response = Headers::from_string(format!(
+        "HTTP/1.1 200 OK{CRLF}Expires: Sun, 06 Nov 2101 08:49:37 GMT{CRLF}{CRLF}"
+    ));
+    let probe = Cache::new().open()?;
+    let body = Body::Length(4);
+    assert!(probe.is_storable(&request("/a"), 200, &response, &body));
+    probe.store("a", &request("/a"), &response, b"body".to_vec())?;
+    let store = Cache::new()
+        .with_max_size(probe.size() * 5 / 2)
+        .with_dir(&dir)
+        .open()?;
+    for key in ["a", "b"] {
+        store.store(
+            key,
+            &request(&format!("/{key}")),
+            &response,
+            key.repeat(4).into_bytes(),
+        )?;
+    }
+    let a = match store.lookup("a", &request("/a")) {
+        Lookup::Hit(cached) => cached,
+        lookup => panic!("Unexpected lookup {:?}", lookup),
+    };
+    assert_eq!(a.body, b"aaaa");
+    store.store("c", &request("/c"), &response, b"cccc".to_vec())?;
+    assert_eq!(store.len(), 2);
+    assert!(matches!(store.lookup("b", &request("/b")), Lookup::Miss));
+    assert!(matches!(store.lookup("c", &request("/c")), Lookup::Hit(_)));
+    assert_eq!(std::fs::read_dir(&dir)?.count(), 2);
+
+    // Expired response without validators is not used
+    let expired = Headers::from_string(format!(
+        "HTTP/1.1 200 OK{CRLF}Date: Sun, 06 Nov 1994 08:49:37 GMT{CRLF}Expires: 0{CRLF}{CRLF}"
+    ));
+    store.store("d", &request("/d"), &expired, vec![])?;
+    assert!(matches!(store.lookup("d", &request("/d")), Lookup::Miss));
+
+    // `Age` of target counts to age of stored response, so it gets stale earlier
+    let aged = Headers::from_string(format!(
+        "HTTP/1.1 200 OK{CRLF}Cache-Control: max-age=60{CRLF}Age: 59{CRLF}ETag: \"e\"{CRLF}{CRLF}"
+    ));
+    let cached = store.store("e", &request("/e"), &aged, vec![])?;
+    assert!(cached.age >= Duration::from_secs(59));
+    assert_eq!(cached.headers.get_header("Age"), None);
+    match store.lookup("e", &request("/e")) {
+        Lookup::Hit(cached) => assert!(cached.age >= Duration::from_secs(59)),
+        lookup => panic!("Unexpected lookup {:?}", lookup),
+    }
+    sleep(Duration::from_millis(1100));
+    assert!(matches!(
+        store.lookup("e", &request("/e")),
+        Lookup::Stale(_)
+    ));
+    let not_modified = Headers::from_string(format!(
+        "HTTP/1.1 304 Not Modified{CRLF}Age: 30{CRLF}{CRLF}"
+    ));
+    let cached = match store.lookup("e", &request("/e")) {
+        Lookup::Stale(cached) => store.revalidate(&cached, &not_modified)?,
+        lookup => panic!("Unexpected lookup {:?}", lookup),
+    };
+    assert!(cached.age >= Duration::from_secs(30));
+    assert!(matches!(store.lookup("e", &request("/e")), Lookup::Hit(_)));
+    assert_eq!(
+        parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+        Some(UNIX_EPOCH + Duration::from_secs(784_111_777))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_interim_response() -> Result<()> {
+    use std::{io::Read, net::TcpStream};
+
+    // Target answers `100 Continue` before reading body and `200` after it
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let target: &'static str = Box::leak(listener.local_addr()?.to_string().into_boxed_str());
+    spawn(move || {
+        for stream in listener.incoming() {
+            let mut http = Http::from(stream.unwrap());
+            let heads = http.read_headers().unwrap();
+            let req = Request::new(
+                Socket {
+                    host: "".to_string(),
+                    peer_addr: "".to_string(),
+                    ttl: TTL_DEFAULT,
+                    error: "".to_string(),
+                },
+                heads,
+            )
+            .unwrap();
+            http.write_all(format!("HTTP/1.1 100 Continue{CRLF}{CRLF}").as_bytes())
+                .unwrap();
+            let body = http.read_body(&req).unwrap();
+            let res = format!(
+                "HTTP/1.1 200 OK{CRLF}Content-Length: {}{CRLF}{CRLF}",
+                body.len()
+            );
+            http.write_all(res.as_bytes()).unwrap();
+            http.write_all(&body).unwrap();
+        }
+    });
+    let handle = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .spawn(None)?;
+
+    let mut stream = TcpStream::connect(handle.local_addr())?;
+    stream.write_all(
+        format!(
+            "POST /upload HTTP/1.1{CRLF}Expect: 100-continue{CRLF}Content-Length: 4{CRLF}Connection: close{CRLF}{CRLF}"
+        )
+        .as_bytes(),
+    )?;
+    let mut head = [0; 25];
+    stream.read_exact(&mut head)?;
+    assert_eq!(
+        &head,
+        format!("HTTP/1.1 100 Continue{CRLF}{CRLF}").as_bytes()
+    );
+    stream.write_all(String::from_iter(ECHO).as_bytes())?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res)?;
+    assert!(res.starts_with("HTTP/1.1 200 OK"));
+    assert!(res.contains("Content-Length: 4"));
+    assert!(res.ends_with("echo"));
+
+    handle.shutdown();
+    handle.join()
+}
+
+#[test]
+fn test_idle_connections() -> Result<()> {
+    use std::{io::Read, net::TcpStream, time::Instant};
+
+    let target = headers_target()?;
+    let handle = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(target)
+        .with_forward_proxy(ForwardProxy::new())
+        .with_threads(2)
+        .spawn(None)?;
+    let address = handle.local_addr();
+    let request = format!("GET / HTTP/1.1{CRLF}Host: proxy{CRLF}{CRLF}");
+    let read_head = |stream: &mut TcpStream| -> Result<String> {
+        let mut http = Http::from(stream.try_clone()?);
+        let head = String::from_utf8_lossy(&http.read_headers()?).to_string();
+        let len = Headers::from_string(head.clone())
+            .content_length()
+            .unwrap_or(0);
+        http.read_exact(&mut vec![0; len])?;
+        Ok(head)
+    };
+
+    // Idle keep-alive connections don't hold threads of pool
+    let mut idle = vec![];
+    for _ in 0..4 {
+        let mut stream = TcpStream::connect(address)?;
+        stream.write_all(request.as_bytes())?;
+        assert!(read_head(&mut stream)?.starts_with("HTTP/1.1 200"));
+        idle.push(stream);
+    }
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(address)?;
+    stream.write_all(request.as_bytes())?;
+    assert!(read_head(&mut stream)?.starts_with("HTTP/1.1 200"));
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    // Parked connection is served again on next request
+    stream.write_all(request.as_bytes())?;
+    assert!(read_head(&mut stream)?.contains("Connection: keep-alive"));
+
+    // One of two threads is kept for requests, so second tunnel is rejected
+    let destination = TcpListener::bind("127.0.0.1:0")?;
+    let connect = format!("CONNECT {} HTTP/1.1{CRLF}{CRLF}", destination.local_addr()?);
+    let mut tunnel = TcpStream::connect(address)?;
+    tunnel.write_all(connect.as_bytes())?;
+    assert!(read_head(&mut tunnel)?.starts_with("HTTP/1.1 200"));
+    let mut rejected = TcpStream::connect(address)?;
+    rejected.write_all(connect.as_bytes())?;
+    let mut res = String::new();
+    rejected.read_to_string(&mut res)?;
+    assert!(res.starts_with("HTTP/1.1 503"));
+    drop(tunnel);
+    drop(destination);
+
+    handle.shutdown();
+    handle.join()
+}
+
+#[test]
+fn test_chunk_framing() -> Result<()> {
+    use std::{io::ErrorKind, net::TcpStream};
+
+    // Chunked body is written to one end of connection and forwarded from the other one
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+    let forward = |body: &str, max_size: usize| -> Result<usize> {
+        let mut writer = TcpStream::connect(address)?;
+        let mut from = Http::from(listener.accept()?.0);
+        writer.write_all(body.as_bytes())?;
+        drop(writer);
+        let mut sink = Http::from(TcpStream::connect(address)?);
+        let _peer = listener.accept()?;
+        sink.forward_body_limited(&mut from, &Body::Chunked, max_size)
+    };
+
+    // Only chunk data is counted by limit
+    assert_eq!(forward(&format!("4{CRLF}echo{CRLF}0{CRLF}{CRLF}"), 4)?, 4);
+    assert_eq!(
+        forward(&format!("2{CRLF}ec{CRLF}2{CRLF}ho{CRLF}0{CRLF}{CRLF}"), 4)?,
+        4
+    );
+    assert_eq!(
+        forward(&format!("4;name=value{CRLF}echo{CRLF}0{CRLF}{CRLF}"), 4)?,
+        4
+    );
+
+    // Sizes which could be read differently and chunks without CRLF are rejected
+    assert!(forward(&format!("ffffffffffffffff{CRLF}echo{CRLF}"), usize::MAX).is_err());
+    for body in [
+        format!("+4{CRLF}echo{CRLF}0{CRLF}{CRLF}"),
+        format!(" 4{CRLF}echo{CRLF}0{CRLF}{CRLF}"),
+        format!("4 {CRLF}echo{CRLF}0{CRLF}{CRLF}"),
+        format!("0x4{CRLF}echo{CRLF}0{CRLF}{CRLF}"),
+        format!("4\necho{CRLF}0{CRLF}{CRLF}"),
+        format!("4{CRLF}echoXX0{CRLF}{CRLF}"),
+        format!("2{CRLF}echo{CRLF}0{CRLF}{CRLF}"),
+    ] {
+        let err = forward(&body, usize::MAX).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{:?}", body);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_target_address() -> Result<()> {
+    let target = Target::parse("https://example.com/")?;
+    assert!(target.tls);
+    assert_eq!(target.authority(), "example.com:443");
+    assert_eq!(target.host_header(), "example.com");
+
+    let target = Target::parse("[::1]:8080")?;
+    assert!(!target.tls);
+    assert_eq!(target.host, "[::1]");
+    assert_eq!(target.host_header(), "[::1]:8080");
+
+    assert!(Target::parse("ftp://example.com").is_err());
+    assert!(Target::parse("example.com:port").is_err());
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_tls_origination() -> Result<()> {
+    use crate::tls::{TlsConfig, UpstreamTls};
+    use std::{fs, io::Read, net::TcpStream};
+
+    let dir = std::env::temp_dir().join("proxy-server-test-tls-origination");
+    fs::create_dir_all(&dir)?;
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_path = dir.join("localhost.crt").to_string_lossy().to_string();
+    let key_path = dir.join("localhost.key").to_string_lossy().to_string();
+    fs::write(&cert_path, cert.cert.pem())?;
+    fs::write(&key_path, cert.key_pair.serialize_pem())?;
+
+    // Target behind proxy terminating TLS
+    let https = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(echo_target()?)
+        .with_tls(TlsConfig::new(&cert_path, &key_path))
+        .spawn(None)?;
+    let target = format!("https://{}", https.local_addr());
+
+    let server = Builder::new()
+        .with_address("127.0.0.1:0")
+        .with_target(Box::leak(target.into_boxed_str()))
+        .with_upstream_tls(
+            UpstreamTls::new()
+                .with_ca(&cert_path)
+                .with_server_name("localhost"),
+        )
+        .spawn(None)?;
+
+    let mut stream = TcpStream::connect(server.local_addr())?;
+    let raw_headers = Headers::new_request(
+        "POST / HTTP/1.1",
+        vec![
+            Header {
+                name: "Content-Length".to_string(),
+                value: ECHO.len().to_string(),
+            },
+            Header {
+                name: "Connection".to_string(),
+                value: "close".to_string(),
+            },
+        ],
+    )
+    .raw()
+    .to_vec();
+    stream.write_all(&raw_headers)?;
+    stream.write_all(String::from_iter(ECHO).as_bytes())?;
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&format!("1{CRLF}o{CRLF}0{CRLF}{CRLF}")));
+
+    for server in [server, https] {
+        server.shutdown();
+        server.join()?;
+    }
+    Ok(())
+}
@@ -1,11 +1,15 @@
+use crate::log::{Log, LogLevel};
 use std::{
     sync::{mpsc, Arc, Mutex},
     thread,
 };
 
+const TAG: &str = "Thread pool";
+
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: Option<mpsc::Sender<Job>>,
+    log: Log,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
@@ -18,7 +22,7 @@ impl ThreadPool {
     /// # Panics
     ///
     /// The `new` function will panic if the size is zero.
-    pub fn new(size: usize) -> ThreadPool {
+    pub fn new(size: usize, log: Log) -> ThreadPool {
         assert!(size > 0);
 
         let (sender, receiver) = mpsc::channel();
@@ -28,12 +32,13 @@ impl ThreadPool {
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&receiver), log.clone()));
         }
 
         ThreadPool {
             workers,
             sender: Some(sender),
+            log,
         }
     }
 
@@ -45,6 +50,30 @@ impl ThreadPool {
 
         self.sender.as_ref().unwrap().send(job).unwrap();
     }
+
+    /// Handle for adding jobs from other threads, pool waits for workers on drop
+    /// only after all handles are dropped
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            sender: self.sender.as_ref().unwrap().clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Spawner {
+    sender: mpsc::Sender<Job>,
+}
+
+impl Spawner {
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+
+        self.sender.send(job).unwrap();
+    }
 }
 
 impl Drop for ThreadPool {
@@ -52,7 +81,8 @@ impl Drop for ThreadPool {
         drop(self.sender.take());
 
         for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+            self.log
+                .println(LogLevel::Debug, TAG, "shutting down worker", worker.id);
 
             if let Some(thread) = worker.thread.take() {
                 thread.join().unwrap();
@@ -67,18 +97,18 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>, log: Log) -> Worker {
         let thread = thread::spawn(move || loop {
             let message = receiver.lock().unwrap().recv();
 
             match message {
                 Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
+                    log.println(LogLevel::Trace, TAG, "worker got a job", id);
 
                     job();
                 }
                 Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
+                    log.println(LogLevel::Debug, TAG, "worker disconnected", id);
                     break;
                 }
             }
@@ -0,0 +1,197 @@
+//! Module [`TlsConfig`].
+//! TLS termination of client connections and TLS to targets, enabled by feature `tls`
+use rustls::{
+    crypto::ring::{default_provider, sign::any_supported_type},
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Error, ErrorKind, Result},
+    sync::Arc,
+};
+
+/// ALPN protocol of HTTP/1.1
+pub const ALPN_HTTP_1_1: &[u8] = b"http/1.1";
+
+/// Certificate chain and private key in PEM files
+#[derive(Clone, Debug)]
+pub struct Certificate {
+    /// Server name for SNI, certificate without name is used by default
+    pub server_name: Option<String>,
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+/// Certificates of proxy server listener
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub certificates: Vec<Certificate>,
+}
+
+impl TlsConfig {
+    /// Create config with default certificate
+    pub fn new(cert_path: &str, key_path: &str) -> Self {
+        Self {
+            certificates: vec![Certificate {
+                server_name: None,
+                cert_path: cert_path.to_string(),
+                key_path: key_path.to_string(),
+            }],
+        }
+    }
+
+    /// Add certificate chosen by SNI server name
+    pub fn with_sni(mut self, server_name: &str, cert_path: &str, key_path: &str) -> Self {
+        self.certificates.push(Certificate {
+            server_name: Some(server_name.to_lowercase()),
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+        });
+        self
+    }
+
+    /// Load certificates and create config of [`rustls`] server with ALPN `http/1.1`
+    pub fn server_config(&self) -> Result<Arc<ServerConfig>> {
+        let mut resolver = CertResolver {
+            by_name: HashMap::new(),
+            default: None,
+        };
+        for cert in &self.certificates {
+            let key = Arc::new(load_certified_key(&cert.cert_path, &cert.key_path)?);
+            match &cert.server_name {
+                Some(name) => {
+                    resolver.by_name.insert(name.clone(), key);
+                }
+                None => resolver.default = Some(key),
+            }
+        }
+
+        let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = vec![ALPN_HTTP_1_1.to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+/// Settings of TLS connections to targets with `https://` scheme
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamTls {
+    /// PEM file of trusted CA certificates, Mozilla root certificates are used if it is missing
+    pub ca_path: Option<String>,
+    /// Client certificate for mutual TLS
+    pub client_cert: Option<Certificate>,
+    /// SNI server name and name of verified certificate instead of target host
+    pub server_name: Option<String>,
+}
+
+impl UpstreamTls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust only CA certificates from PEM file
+    pub fn with_ca(mut self, ca_path: &str) -> Self {
+        self.ca_path = Some(ca_path.to_string());
+        self
+    }
+
+    /// Authenticate proxy server to targets with client certificate
+    pub fn with_client_cert(mut self, cert_path: &str, key_path: &str) -> Self {
+        self.client_cert = Some(Certificate {
+            server_name: None,
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+        });
+        self
+    }
+
+    /// Override SNI server name of targets
+    pub fn with_server_name(mut self, server_name: &str) -> Self {
+        self.server_name = Some(server_name.to_string());
+        self
+    }
+
+    /// Load certificates and create config of [`rustls`] client with ALPN `http/1.1`
+    pub fn client_config(&self) -> Result<Arc<ClientConfig>> {
+        let mut roots = RootCertStore::empty();
+        match &self.ca_path {
+            Some(path) => {
+                for cert in load_certs(path)? {
+                    roots
+                        .add(cert)
+                        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+
+        let builder = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?
+            .with_root_certificates(roots);
+        let mut config = match &self.client_cert {
+            Some(cert) => builder
+                .with_client_auth_cert(load_certs(&cert.cert_path)?, load_key(&cert.key_path)?)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?,
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![ALPN_HTTP_1_1.to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+/// Choosing of certificate by SNI server name with fallback to default one
+#[derive(Debug)]
+struct CertResolver {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_lowercase()))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+/// Read certificate chain from PEM file
+pub fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Certificates didn't find in {}", path),
+        ));
+    }
+    Ok(certs)
+}
+
+/// Read private key from PEM file
+pub fn load_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    match rustls_pemfile::private_key(&mut reader)? {
+        Some(key) => Ok(key),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Private key didn't find in {}", path),
+        )),
+    }
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey> {
+    let certs = load_certs(cert_path)?;
+    let key = load_key(key_path)?;
+    let key = any_supported_type(&key).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    Ok(CertifiedKey::new(certs, key))
+}
@@ -0,0 +1,346 @@
+//! Module [`Balancer`].
+//! Groups of targets and strategies of choosing target for request
+use crate::http::request::Request;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+/// Count of points of one target with weight `1` on consistent hash ring
+const RING_POINTS: u32 = 160;
+
+/// Target server of upstream group
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    pub address: String,
+    pub weight: u32,
+}
+
+impl Upstream {
+    /// Create target with weight `1`
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            weight: 1,
+        }
+    }
+
+    /// Set weight used by weighted strategies
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+}
+
+/// Source of key for consistent hashing
+#[derive(Clone, Debug)]
+pub enum HashKey {
+    /// IP address of client without port
+    ClientIp,
+    /// Value of request header, client IP is used when header is missing
+    Header(String),
+}
+
+/// Strategy of choosing target from group
+#[derive(Clone, Debug)]
+pub enum Strategy {
+    RoundRobin,
+    WeightedRoundRobin,
+    LeastConnections,
+    Random,
+    ConsistentHash(HashKey),
+}
+
+/// Targets with strategy of load balancing between them
+#[derive(Clone, Debug)]
+pub struct UpstreamGroup {
+    pub upstreams: Vec<Upstream>,
+    pub strategy: Strategy,
+}
+
+impl UpstreamGroup {
+    /// Create group with round robin strategy
+    pub fn new(upstreams: Vec<Upstream>) -> Self {
+        Self {
+            upstreams,
+            strategy: Strategy::RoundRobin,
+        }
+    }
+
+    /// Set strategy of load balancing
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+}
+
+/// Runtime state of target
+#[derive(Debug)]
+pub struct UpstreamState {
+    pub upstream: Upstream,
+    active: AtomicUsize,
+    healthy: AtomicBool,
+    draining: AtomicBool,
+    failures: AtomicU32,
+    successes: AtomicU32,
+}
+
+impl UpstreamState {
+    fn new(upstream: Upstream) -> Self {
+        Self {
+            upstream,
+            active: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+            draining: AtomicBool::new(false),
+            failures: AtomicU32::new(0),
+            successes: AtomicU32::new(0),
+        }
+    }
+
+    /// Count of requests in progress
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Target is used by balancer
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    /// Target doesn't get new requests, requests in progress are finished
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Stop choosing target for new requests
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Target can be chosen for new request
+    fn is_available(&self) -> bool {
+        self.is_healthy() && !self.is_draining()
+    }
+
+    /// Count succeeded check, returns `true` when target becomes healthy
+    pub fn report_success(&self, threshold: u32) -> bool {
+        self.failures.store(0, Ordering::SeqCst);
+        let successes = self.successes.fetch_add(1, Ordering::SeqCst) + 1;
+        successes >= threshold && !self.healthy.swap(true, Ordering::SeqCst)
+    }
+
+    /// Count failed check, returns `true` when target becomes unhealthy
+    pub fn report_failure(&self, threshold: u32) -> bool {
+        self.successes.store(0, Ordering::SeqCst);
+        let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        failures >= threshold && self.healthy.swap(false, Ordering::SeqCst)
+    }
+}
+
+struct Targets {
+    list: Vec<Arc<UpstreamState>>,
+    /// Current weights of smooth weighted round robin
+    weights: Mutex<Vec<i64>>,
+    /// Sorted points of consistent hash ring with index of target
+    ring: Vec<(u64, usize)>,
+}
+
+/// Thread safe balancer choosing target of [`UpstreamGroup`] for every request
+pub struct Balancer {
+    strategy: Strategy,
+    targets: RwLock<Targets>,
+    next: AtomicUsize,
+}
+
+/// Target chosen for request, it is counted as active until dropped
+#[derive(Debug)]
+pub struct Selected {
+    state: Arc<UpstreamState>,
+}
+
+impl Balancer {
+    pub fn new(group: UpstreamGroup) -> Self {
+        let balancer = Self {
+            strategy: group.strategy,
+            targets: RwLock::new(Targets {
+                list: vec![],
+                weights: Mutex::new(vec![]),
+                ring: vec![],
+            }),
+            next: AtomicUsize::new(0),
+        };
+        balancer.set_upstreams(group.upstreams);
+        balancer
+    }
+
+    /// Replace targets of group, requests in progress keep their targets
+    pub fn set_upstreams(&self, upstreams: Vec<Upstream>) {
+        let list = upstreams
+            .into_iter()
+            .map(|upstream| Arc::new(UpstreamState::new(upstream)))
+            .collect();
+        self.set_states(list);
+    }
+
+    /// Add target to group, other targets keep their state
+    pub fn add_upstream(&self, upstream: Upstream) -> Arc<UpstreamState> {
+        let state = Arc::new(UpstreamState::new(upstream));
+        let mut list = self.upstreams();
+        list.push(Arc::clone(&state));
+        self.set_states(list);
+        state
+    }
+
+    /// Stop choosing target with address for new requests, returns `false` if it is missing
+    pub fn drain(&self, address: &str) -> bool {
+        let list = self.upstreams();
+        let found: Vec<_> = list
+            .iter()
+            .filter(|state| state.upstream.address == address)
+            .collect();
+        for state in &found {
+            state.drain();
+        }
+        !found.is_empty()
+    }
+
+    fn set_states(&self, list: Vec<Arc<UpstreamState>>) {
+        let mut ring = vec![];
+        for (i, state) in list.iter().enumerate() {
+            for p in 0..RING_POINTS.saturating_mul(state.upstream.weight) {
+                let point = format!("{}#{}", state.upstream.address, p);
+                ring.push((hash(point.as_bytes()), i));
+            }
+        }
+        ring.sort();
+        let mut targets = self.targets.write().unwrap();
+        *targets = Targets {
+            weights: Mutex::new(vec![0; list.len()]),
+            list,
+            ring,
+        };
+    }
+
+    /// Current targets
+    pub fn upstreams(&self) -> Vec<Arc<UpstreamState>> {
+        self.targets.read().unwrap().list.clone()
+    }
+
+    /// Choose healthy target which is not drained for request,
+    /// returns [`None`] if there are no such targets
+    pub fn select(&self, req: &Request) -> Option<Selected> {
+        let targets = self.targets.read().unwrap();
+        let healthy: Vec<usize> = (0..targets.list.len())
+            .filter(|i| targets.list[*i].is_available())
+            .collect();
+        if healthy.is_empty() {
+            return None;
+        }
+        let index = match &self.strategy {
+            Strategy::RoundRobin => {
+                healthy[self.next.fetch_add(1, Ordering::SeqCst) % healthy.len()]
+            }
+            Strategy::WeightedRoundRobin => Balancer::select_weighted(&targets, &healthy),
+            Strategy::LeastConnections => self.select_least(&targets, &healthy),
+            Strategy::Random => healthy[(random() % healthy.len() as u64) as usize],
+            Strategy::ConsistentHash(key) => Balancer::select_hash(&targets, key, req)?,
+        };
+        let state = Arc::clone(&targets.list[index]);
+        state.active.fetch_add(1, Ordering::SeqCst);
+        Some(Selected { state })
+    }
+
+    /// Smooth weighted round robin
+    fn select_weighted(targets: &Targets, healthy: &[usize]) -> usize {
+        let mut weights = targets.weights.lock().unwrap();
+        let mut total: i64 = 0;
+        let mut best = healthy[0];
+        for i in healthy {
+            let weight = targets.list[*i].upstream.weight as i64;
+            total += weight;
+            weights[*i] += weight;
+            if weights[*i] > weights[best] {
+                best = *i;
+            }
+        }
+        weights[best] -= total;
+        best
+    }
+
+    fn select_least(&self, targets: &Targets, healthy: &[usize]) -> usize {
+        let start = self.next.fetch_add(1, Ordering::SeqCst);
+        let len = healthy.len();
+        let mut best = healthy[start % len];
+        for i in 0..len {
+            let index = healthy[(start + i) % len];
+            let state = &targets.list[index];
+            let best_state = &targets.list[best];
+            // Compare active / weight without division
+            if state.active() * (best_state.upstream.weight.max(1) as usize)
+                < best_state.active() * (state.upstream.weight.max(1) as usize)
+            {
+                best = index;
+            }
+        }
+        best
+    }
+
+    /// Target of the first available point of ring after hash of key,
+    /// targets with weight `0` don't have points
+    fn select_hash(targets: &Targets, key: &HashKey, req: &Request) -> Option<usize> {
+        let client_ip = match req.peer_addr.parse::<SocketAddr>() {
+            Ok(addr) => addr.ip().to_string(),
+            Err(_) => req.peer_addr.clone(),
+        };
+        let value = match key {
+            HashKey::ClientIp => client_ip,
+            HashKey::Header(name) => req.headers.get_header(name).unwrap_or(client_ip),
+        };
+        let point = hash(value.as_bytes());
+        let pos = targets.ring.partition_point(|(p, _)| *p < point);
+        // Walk the ring clockwise up to the first available target
+        let len = targets.ring.len();
+        for i in 0..len {
+            let (_, index) = targets.ring[(pos + i) % len];
+            if targets.list[index].is_available() {
+                return Some(index);
+            }
+        }
+        None
+    }
+}
+
+impl Selected {
+    /// Address of chosen target
+    pub fn address(&self) -> &str {
+        &self.state.upstream.address
+    }
+}
+
+impl Drop for Selected {
+    fn drop(&mut self) {
+        self.state.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// FNV-1a hash, it is stable between runs unlike [`std::collections::hash_map::DefaultHasher`]
+fn hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in data {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Random number from keys of [`RandomState`] which are different for every instance
+fn random() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(0);
+    hasher.finish()
+}